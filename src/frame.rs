@@ -1,3 +1,20 @@
+use core::time::Duration;
+
+#[cfg(feature = "embedded-hal")]
+use embedded_hal::can::Id;
+
+/// Maximum data length of a classic CAN frame.
+pub const CAN_DL: usize = 8;
+
+/// Maximum data length of a CAN FD frame.
+pub const CAN_FD_DL: usize = 64;
+
+/// Maximum message length encoded without the first frame escape sequence.
+const MAX_FIRST_LEN: u32 = 0xFFF;
+
+/// Valid CAN FD data lengths above 8 bytes.
+const FD_DLS: [usize; 7] = [12, 16, 20, 24, 32, 48, 64];
+
 #[repr(u8)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Kind {
@@ -12,76 +29,538 @@ pub enum Kind {
 pub enum FlowKind {
     Continue,
     Wait,
-    Abort,
+    /// The message is larger than the receiver can accept.
+    Overflow,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct Frame {
-    bytes: [u8; 8],
+impl FlowKind {
+    fn from_bits(bits: u8) -> Option<Self> {
+        let kind = match bits {
+            0 => FlowKind::Continue,
+            1 => FlowKind::Wait,
+            2 => FlowKind::Overflow,
+            _ => return None,
+        };
+
+        Some(kind)
+    }
 }
 
-impl Frame {
-    pub fn from_bytes(bytes: &[u8]) -> Self {
-        let mut buf = [0; 8];
-        for (a, b) in bytes.iter().zip(&mut buf) {
-            *b = *a;
+/// Minimum separation time (STmin) between consecutive frames.
+///
+/// This is encoded in flow control frames as 0-127ms with `0x00`-`0x7F`,
+/// or 100-900µs with `0xF1`-`0xF9`. Reserved values are treated as 127ms.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SeparationTime(u8);
+
+impl SeparationTime {
+    /// No separation time between consecutive frames.
+    pub const ZERO: Self = Self(0);
+
+    /// The longest separation time of 127ms.
+    pub const MAX: Self = Self(0x7F);
+
+    /// Create a separation time from its encoded byte.
+    pub const fn from_bits(bits: u8) -> Self {
+        Self(bits)
+    }
+
+    /// Returns the encoded byte of this separation time.
+    pub const fn to_bits(self) -> u8 {
+        self.0
+    }
+
+    /// Returns `true` if this is a reserved value.
+    pub fn is_reserved(&self) -> bool {
+        !matches!(self.0, 0x00..=0x7F | 0xF1..=0xF9)
+    }
+}
+
+impl From<u8> for SeparationTime {
+    fn from(bits: u8) -> Self {
+        Self(bits)
+    }
+}
+
+impl From<SeparationTime> for u8 {
+    fn from(st: SeparationTime) -> Self {
+        st.0
+    }
+}
+
+/// Encode a duration, rounding times under a millisecond up to the next 100µs
+/// and limiting longer times to 127ms.
+impl From<Duration> for SeparationTime {
+    fn from(st: Duration) -> Self {
+        if st.is_zero() {
+            Self::ZERO
+        } else if st <= Duration::from_micros(900) {
+            // 100-900 microseconds are encoded as 0xF1-0xF9
+            Self(0xF0 + st.as_micros().div_ceil(100) as u8)
+        } else {
+            Self(st.as_micros().div_ceil(1000).min(0x7F) as u8)
         }
+    }
+}
 
-        Self { bytes: buf }
+impl From<SeparationTime> for Duration {
+    fn from(st: SeparationTime) -> Self {
+        match st.0 {
+            0x00..=0x7F => Duration::from_millis(st.0.into()),
+            0xF1..=0xF9 => Duration::from_micros(u64::from(st.0 - 0xF0) * 100),
+            // Reserved values are treated as the longest separation time
+            _ => SeparationTime::MAX.into(),
+        }
     }
+}
 
-    pub fn single(data: &[u8]) -> Option<Self> {
-        if data.len() > 7 {
-            return None;
+/// Error returned when decoding a malformed frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// The frame contains no bytes.
+    Empty,
+    /// The frame ended before the end of its protocol control information.
+    Truncated,
+    /// The frame type in the high nibble of the first byte is reserved.
+    UnknownKind(u8),
+    /// The flow status of a flow control frame is reserved.
+    UnknownFlowKind(u8),
+    /// The data length of a single frame is zero or larger than the frame.
+    InvalidSingleLength(u8),
+    /// The message length of a first frame is too small to need segmentation.
+    InvalidFirstLength(u32),
+    /// The frame isn't padded as expected.
+    InvalidPadding,
+}
+
+/// Decoded protocol data unit of a [`Frame`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pdu<'a> {
+    /// A complete message in one frame.
+    Single { data: &'a [u8] },
+    /// The start of a segmented message of `len` bytes.
+    First { len: u32, data: &'a [u8] },
+    /// The continuation of a segmented message with a sequence number.
+    Consecutive { index: u8, data: &'a [u8] },
+    /// Flow control sent by the receiver of a segmented message.
+    Flow {
+        kind: FlowKind,
+        block_len: u8,
+        st: SeparationTime,
+    },
+}
+
+impl<'a> TryFrom<&'a [u8]> for Pdu<'a> {
+    type Error = ParseError;
+
+    fn try_from(bytes: &'a [u8]) -> Result<Self, Self::Error> {
+        Self::parse(bytes, 0)
+    }
+}
+
+impl<'a> Pdu<'a> {
+    /// Decode the protocol control information in `bytes`,
+    /// which follows an address byte in frames with an `offset` of one.
+    fn parse(bytes: &'a [u8], offset: usize) -> Result<Self, ParseError> {
+        let pci = *bytes.first().ok_or(ParseError::Empty)?;
+        let low = pci & 0b1111;
+
+        let pdu = match pci >> 4 {
+            0 => {
+                let (len, start) = if low != 0 {
+                    (low, 1)
+                } else if bytes.len() > CAN_DL {
+                    // CAN FD escape sequence
+                    (bytes[1], 2)
+                } else {
+                    return Err(ParseError::InvalidSingleLength(0));
+                };
+
+                let data = bytes
+                    .get(start..start + len as usize)
+                    .filter(|_| len != 0 && (start == 2 || len < CAN_DL as u8))
+                    .ok_or(ParseError::InvalidSingleLength(len))?;
+                Pdu::Single { data }
+            }
+            1 => {
+                let second = *bytes.get(1).ok_or(ParseError::Truncated)?;
+                let (len, start) = if low == 0 && second == 0 {
+                    let escaped = bytes.get(2..6).ok_or(ParseError::Truncated)?;
+                    let mut len = [0; 4];
+                    len.copy_from_slice(escaped);
+
+                    let len = u32::from_be_bytes(len);
+                    if len <= MAX_FIRST_LEN {
+                        return Err(ParseError::InvalidFirstLength(len));
+                    }
+                    (len, 6)
+                } else {
+                    ((low as u32) << 8 | second as u32, 2)
+                };
+
+                // Messages that fit in a single frame can't be segmented
+                if len < (CAN_DL - offset) as u32 {
+                    return Err(ParseError::InvalidFirstLength(len));
+                }
+
+                let data = &bytes[start..];
+                Pdu::First {
+                    len,
+                    data: &data[..data.len().min(len as usize)],
+                }
+            }
+            2 => Pdu::Consecutive {
+                index: low,
+                data: &bytes[1..],
+            },
+            3 => {
+                let params = bytes.get(1..3).ok_or(ParseError::Truncated)?;
+                Pdu::Flow {
+                    kind: FlowKind::from_bits(low).ok_or(ParseError::UnknownFlowKind(low))?,
+                    block_len: params[0],
+                    st: SeparationTime(params[1]),
+                }
+            }
+            kind => return Err(ParseError::UnknownKind(kind)),
+        };
+
+        Ok(pdu)
+    }
+}
+
+/// Addressing mode of a frame.
+///
+/// Extended and mixed addressing place an address byte before the protocol control information,
+/// lowering the payload of each frame by one byte.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Addressing {
+    /// Normal addressing, using only the CAN identifier.
+    #[default]
+    Normal,
+    /// Extended addressing with the target address (N_TA) in the first byte.
+    ///
+    /// Transmitted frames start with `target` and received frames are expected to start with `source`.
+    Extended { target: u8, source: u8 },
+    /// Mixed addressing with the address extension (N_AE) in the first byte.
+    Mixed(u8),
+}
+
+impl Addressing {
+    /// Returns the address byte of transmitted frames.
+    pub fn tx_address(&self) -> Option<u8> {
+        match self {
+            Self::Normal => None,
+            Self::Extended { target, .. } => Some(*target),
+            Self::Mixed(extension) => Some(*extension),
         }
+    }
 
-        let mut bytes = [0; 8];
-        bytes[0] = ((Kind::Single as u8) << 4) | data.len() as u8;
-        for (a, b) in data.iter().zip(&mut bytes[1..]) {
-            *b = *a;
+    /// Returns the address byte expected in received frames.
+    pub fn rx_address(&self) -> Option<u8> {
+        match self {
+            Self::Normal => None,
+            Self::Extended { source, .. } => Some(*source),
+            Self::Mixed(extension) => Some(*extension),
+        }
+    }
+}
+
+/// Padding of transmitted frames.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Padding {
+    /// Pad frames to 8 bytes, or the next valid CAN FD data length, with this byte.
+    Byte(u8),
+    /// Only send the bytes in use, lowering the data length of classic CAN frames.
+    ///
+    /// CAN FD frames over 8 bytes are still padded to the next valid data length with `0xCC`.
+    Disabled,
+}
+
+impl Padding {
+    /// Byte padding CAN FD frames when padding is disabled.
+    const FD_BYTE: u8 = 0xCC;
+
+    /// Returns the data length of a frame using `len` bytes, and the byte to pad it with.
+    fn pad(&self, len: usize) -> (usize, u8) {
+        let padded_len = if len <= CAN_DL {
+            CAN_DL
+        } else {
+            FD_DLS
+                .into_iter()
+                .find(|dl| *dl >= len)
+                .unwrap_or(CAN_FD_DL)
+        };
+
+        match self {
+            Self::Byte(byte) => (padded_len, *byte),
+            Self::Disabled if len <= CAN_DL => (len, Self::FD_BYTE),
+            Self::Disabled => (padded_len, Self::FD_BYTE),
         }
+    }
+}
 
-        Some(Self { bytes })
+impl Default for Padding {
+    fn default() -> Self {
+        Self::Byte(0)
     }
+}
 
-    pub fn first(data: &[u8]) -> (Self, usize) {
-        let mut bytes = [0; 8];
-        bytes[0] = ((Kind::First as u8) << 4) | (data.len() & 0b1111) as u8;
-        bytes[1] = (data.len() >> 4) as u8;
+/// Format of transmitted frames.
+///
+/// This sets the maximum data length (TX_DL) used to segment messages,
+/// and the addressing mode and padding of each frame.
+/// Classic CAN uses 8 bytes, CAN FD may use up to 64.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Format {
+    tx_dl: u8,
+    addressing: Addressing,
+    padding: Padding,
+}
 
-        let mut used = 0;
-        for (a, b) in data.iter().zip(&mut bytes[2..]) {
-            *b = *a;
-            used += 1;
+impl Format {
+    /// Classic CAN frames of 8 bytes.
+    pub const CAN: Self = Self {
+        tx_dl: CAN_DL as _,
+        addressing: Addressing::Normal,
+        padding: Padding::Byte(0),
+    };
+
+    /// CAN FD frames of up to 64 bytes.
+    pub const CAN_FD: Self = Self {
+        tx_dl: CAN_FD_DL as _,
+        addressing: Addressing::Normal,
+        padding: Padding::Byte(0),
+    };
+
+    /// Create a new format for frames of up to `tx_dl` bytes.
+    /// This returns `None` if `tx_dl` is not a valid CAN data length of at least 8.
+    pub fn new(tx_dl: usize) -> Option<Self> {
+        if tx_dl == CAN_DL || FD_DLS.contains(&tx_dl) {
+            Some(Self {
+                tx_dl: tx_dl as _,
+                ..Self::CAN
+            })
+        } else {
+            None
         }
+    }
+
+    /// Use `addressing` for frames of this format.
+    pub const fn with_addressing(mut self, addressing: Addressing) -> Self {
+        self.addressing = addressing;
+        self
+    }
 
-        (Self { bytes }, used)
+    /// Pad frames of this format with `padding`.
+    pub const fn with_padding(mut self, padding: Padding) -> Self {
+        self.padding = padding;
+        self
     }
 
-    pub fn consecutive(index: u8, data: &[u8]) -> (Self, usize) {
-        let mut bytes = [0; 8];
-        bytes[0] = ((Kind::Consecutive as u8) << 4) | index;
+    /// Returns the maximum data length of transmitted frames.
+    pub fn tx_dl(&self) -> usize {
+        self.tx_dl as _
+    }
+
+    /// Returns the addressing mode of this format.
+    pub fn addressing(&self) -> Addressing {
+        self.addressing
+    }
+
+    /// Returns the padding of this format.
+    pub fn padding(&self) -> Padding {
+        self.padding
+    }
 
-        let mut used = 0;
-        for (a, b) in data.iter().zip(&mut bytes[1..]) {
+    /// Decode a frame received in this format,
+    /// or `None` if its address byte is for another node with extended or mixed addressing.
+    pub fn decode(&self, data: &[u8]) -> Option<Frame> {
+        let rx_address = self.addressing.rx_address();
+        let frame = if rx_address.is_some() {
+            Frame::from_addressed_bytes(data)
+        } else {
+            Frame::from_bytes(data)
+        };
+
+        (frame.address() == rx_address).then_some(frame)
+    }
+
+    /// Returns `true` if `frame` was created with the addressing of this format.
+    #[cfg(feature = "socket")]
+    pub(crate) fn is_tx_frame(&self, frame: &Frame) -> bool {
+        frame.address() == self.addressing.tx_address()
+    }
+
+    /// Create a single frame, or `None` if `data` is empty or doesn't fit in one frame.
+    ///
+    /// Payloads that don't fit in a classic CAN frame use the CAN FD escape sequence,
+    /// with the length in the second byte.
+    pub fn single(&self, data: &[u8]) -> Option<Frame> {
+        let mut frame = Frame::empty(self.addressing);
+        let pci = frame.offset();
+
+        if data.is_empty() {
+            return None;
+        } else if pci + 1 + data.len() <= CAN_DL {
+            frame.bytes[pci] = ((Kind::Single as u8) << 4) | data.len() as u8;
+            frame.push(pci + 1, data, self.padding);
+        } else if self.tx_dl() > CAN_DL && pci + 2 + data.len() <= self.tx_dl() {
+            frame.bytes[pci] = (Kind::Single as u8) << 4;
+            frame.bytes[pci + 1] = data.len() as u8;
+            frame.push(pci + 2, data, self.padding);
+        } else {
+            return None;
+        }
+
+        Some(frame)
+    }
+
+    /// Create a first frame, returning the frame and the number of bytes used from `data`.
+    ///
+    /// Messages larger than 4095 bytes use the escape sequence,
+    /// with the length as a 32-bit integer after a zero 12-bit length.
+    pub fn first(&self, data: &[u8]) -> (Frame, usize) {
+        let mut frame = Frame::empty(self.addressing);
+        let pci = frame.offset();
+        let len = data.len() as u32;
+
+        let start = if len <= MAX_FIRST_LEN {
+            frame.bytes[pci] = ((Kind::First as u8) << 4) | (len >> 8) as u8;
+            frame.bytes[pci + 1] = len as u8;
+            pci + 2
+        } else {
+            frame.bytes[pci] = (Kind::First as u8) << 4;
+            frame.bytes[pci + 2..pci + 6].copy_from_slice(&len.to_be_bytes());
+            pci + 6
+        };
+
+        let used = frame.push(
+            start,
+            &data[..data.len().min(self.tx_dl() - start)],
+            self.padding,
+        );
+        (frame, used)
+    }
+
+    /// Create a consecutive frame, returning the frame and the number of bytes used from `data`.
+    /// This returns `None` if `index` is not a 4-bit sequence number.
+    pub fn consecutive(&self, index: u8, data: &[u8]) -> Option<(Frame, usize)> {
+        if index > 0b1111 {
+            return None;
+        }
+
+        let mut frame = Frame::empty(self.addressing);
+        let pci = frame.offset();
+        frame.bytes[pci] = ((Kind::Consecutive as u8) << 4) | index;
+
+        let used = frame.push(
+            pci + 1,
+            &data[..data.len().min(self.tx_dl() - pci - 1)],
+            self.padding,
+        );
+        Some((frame, used))
+    }
+
+    /// Create a flow control frame.
+    pub fn flow(&self, kind: FlowKind, block_len: u8, st: impl Into<SeparationTime>) -> Frame {
+        let mut frame = Frame::empty(self.addressing);
+        let pci = frame.offset();
+        frame.bytes[pci] = ((Kind::Flow as u8) << 4) | kind as u8;
+        frame.bytes[pci + 1] = block_len;
+        frame.bytes[pci + 2] = st.into().to_bits();
+        frame.push(pci + 3, &[], self.padding);
+
+        frame
+    }
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Self::CAN
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    bytes: [u8; CAN_FD_DL],
+    len: u8,
+    has_address: bool,
+    #[cfg(feature = "embedded-hal")]
+    id: Option<Id>,
+}
+
+impl Frame {
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut buf = [0; CAN_FD_DL];
+        let mut len = 0;
+        for (a, b) in bytes.iter().zip(&mut buf) {
             *b = *a;
-            used += 1;
+            len += 1;
         }
 
-        (Self { bytes }, used)
+        Self {
+            bytes: buf,
+            len,
+            has_address: false,
+            #[cfg(feature = "embedded-hal")]
+            id: None,
+        }
     }
 
-    pub fn flow(kind: FlowKind, block_len: u8, st: u8) -> Self {
-        let mut bytes = [0; 8];
-        bytes[0] = ((Kind::Flow as u8) << 4) | kind as u8;
-        bytes[1] = block_len;
-        bytes[2] = st;
+    /// Create a frame from `bytes` received with extended or mixed addressing,
+    /// where the first byte is an address.
+    pub fn from_addressed_bytes(bytes: &[u8]) -> Self {
+        let mut frame = Self::from_bytes(bytes);
+        frame.has_address = true;
+        frame
+    }
 
-        Self { bytes }
+    /// Set the CAN identifier this frame was received with.
+    #[cfg(feature = "embedded-hal")]
+    pub fn with_id(mut self, id: impl Into<Id>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Returns the CAN identifier this frame was received with, if known.
+    #[cfg(feature = "embedded-hal")]
+    pub fn id(&self) -> Option<Id> {
+        self.id
+    }
+
+    pub fn single(data: &[u8]) -> Option<Self> {
+        Format::CAN.single(data)
+    }
+
+    pub fn first(data: &[u8]) -> (Self, usize) {
+        Format::CAN.first(data)
+    }
+
+    pub fn consecutive(index: u8, data: &[u8]) -> Option<(Self, usize)> {
+        Format::CAN.consecutive(index, data)
+    }
+
+    pub fn flow(kind: FlowKind, block_len: u8, st: impl Into<SeparationTime>) -> Self {
+        Format::CAN.flow(kind, block_len, st)
+    }
+
+    /// Decode the protocol control information of this frame.
+    pub fn parse(&self) -> Result<Pdu<'_>, ParseError> {
+        let bytes = self.as_ref().get(self.offset()..).unwrap_or(&[]);
+        Pdu::parse(bytes, self.offset())
+    }
+
+    /// Returns the address byte of this frame if it uses extended or mixed addressing.
+    pub fn address(&self) -> Option<u8> {
+        if self.has_address && !self.is_empty() {
+            Some(self.bytes[0])
+        } else {
+            None
+        }
     }
 
     pub fn kind(&self) -> Option<Kind> {
-        let kind = match (self.bytes[0] >> 4) & 0b00001111 {
+        let kind = match (self.pci()[0] >> 4) & 0b00001111 {
             0 => Kind::Single,
             1 => Kind::First,
             2 => Kind::Consecutive,
@@ -93,42 +572,151 @@ impl Frame {
     }
 
     pub fn single_data(&self) -> &[u8] {
-        let len = self.bytes[0] & 0b1111;
-        &self.bytes[1..=len as usize]
+        let len = self.pci()[0] & 0b1111;
+        if len == 0 && self.len() > CAN_DL {
+            // CAN FD escape sequence
+            let len = self.pci()[1] as usize;
+            self.data(2, 2 + len)
+        } else {
+            self.data(1, 1 + len as usize)
+        }
     }
 
     pub fn consecutive_data(&self) -> &[u8] {
-        &self.bytes[1..]
+        self.data(1, self.len())
     }
 
     pub fn first_data(&self) -> &[u8] {
-        &self.bytes[2..]
+        let start = if self.is_first_escaped() { 6 } else { 2 };
+        self.data(start, self.len())
     }
 
-    pub fn first_len(&self) -> u16 {
-        ((self.bytes[0] & 0b1111) as u16) | ((self.bytes[1] as u16) << 4)
+    pub fn first_len(&self) -> u32 {
+        let pci = self.pci();
+        if self.is_first_escaped() {
+            let mut bytes = [0; 4];
+            bytes.copy_from_slice(&pci[2..6]);
+            u32::from_be_bytes(bytes)
+        } else {
+            (((pci[0] & 0b1111) as u32) << 8) | pci[1] as u32
+        }
     }
 
-    pub fn flow_kind(&self) -> FlowKind {
-        match self.bytes[0] & 011 {
-            0 => FlowKind::Continue,
-            1 => FlowKind::Wait,
-            2 => FlowKind::Abort,
-            _ => todo!(),
-        }
+    pub fn flow_kind(&self) -> Option<FlowKind> {
+        FlowKind::from_bits(self.pci()[0] & 0b1111)
     }
 
     pub fn flow_len(&self) -> u8 {
-        self.bytes[1]
+        self.pci()[1]
+    }
+
+    pub fn flow_st(&self) -> SeparationTime {
+        SeparationTime(self.pci()[2])
+    }
+
+    /// Check that this frame is padded with `padding`.
+    ///
+    /// The end of the data in consecutive frames depends on the message length,
+    /// so their padding isn't checked.
+    pub fn check_padding(&self, padding: Padding) -> Result<(), ParseError> {
+        let used = match self.parse()? {
+            Pdu::Single { data } => {
+                let pci_len = if self.pci()[0] & 0b1111 == 0 { 2 } else { 1 };
+                self.offset() + pci_len + data.len()
+            }
+            Pdu::Flow { .. } => self.offset() + 3,
+            Pdu::First { .. } | Pdu::Consecutive { .. } => self.len(),
+        };
+        self.check_padding_after(used, padding)
+    }
+
+    /// Check that the bytes of this frame after the first `used` are padded with `padding`.
+    pub(crate) fn check_padding_after(
+        &self,
+        used: usize,
+        padding: Padding,
+    ) -> Result<(), ParseError> {
+        if used == self.len() {
+            // Frames full of data need no padding
+            return Ok(());
+        }
+
+        let (padded_len, byte) = padding.pad(used);
+        if self.len() == padded_len && self.bytes[used..padded_len].iter().all(|b| *b == byte) {
+            Ok(())
+        } else {
+            Err(ParseError::InvalidPadding)
+        }
+    }
+
+    /// Returns the data length (CAN_DL) of this frame.
+    pub fn len(&self) -> usize {
+        self.len as _
+    }
+
+    /// Returns `true` if this frame contains no bytes.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns `true` if this first frame uses the escape sequence for messages over 4095 bytes.
+    fn is_first_escaped(&self) -> bool {
+        let pci = self.pci();
+        pci[0] & 0b1111 == 0 && pci[1] == 0
+    }
+
+    /// Create an empty frame to be filled by a [`Format`].
+    fn empty(addressing: Addressing) -> Self {
+        let mut bytes = [0; CAN_FD_DL];
+        let address = addressing.tx_address();
+        if let Some(address) = address {
+            bytes[0] = address;
+        }
+
+        Self {
+            bytes,
+            len: 0,
+            has_address: address.is_some(),
+            #[cfg(feature = "embedded-hal")]
+            id: None,
+        }
+    }
+
+    /// Returns the index of the protocol control information.
+    fn offset(&self) -> usize {
+        self.has_address as _
+    }
+
+    /// Returns the bytes of this frame starting with the protocol control information.
+    fn pci(&self) -> &[u8] {
+        &self.bytes[self.offset()..]
+    }
+
+    /// Copy `data` into this frame starting at `start`,
+    /// then pad the frame to the next valid data length.
+    fn push(&mut self, start: usize, data: &[u8], padding: Padding) -> usize {
+        self.bytes[start..start + data.len()].copy_from_slice(data);
+
+        let len = start + data.len();
+        let (padded_len, byte) = padding.pad(len);
+        self.bytes[len..padded_len].fill(byte);
+        self.len = padded_len as _;
+
+        data.len()
     }
 
-    pub fn flow_st(&self) -> u8 {
-        self.bytes[2]
+    /// Returns the bytes from `start` to `end` after the protocol control information,
+    /// bounded by the length of this frame.
+    fn data(&self, start: usize, end: usize) -> &[u8] {
+        let offset = self.offset();
+        self.bytes
+            .get(offset + start..(offset + end).min(self.len()))
+            .unwrap_or(&[])
     }
 }
 
 impl AsRef<[u8]> for Frame {
     fn as_ref(&self) -> &[u8] {
-        &self.bytes
+        &self.bytes[..self.len()]
     }
 }
@@ -1,12 +1,26 @@
 #![cfg_attr(not(test), no_std)]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
 pub mod frame;
 pub use frame::Frame;
 
+#[cfg(feature = "socket")]
+pub mod mux;
+#[cfg(feature = "socket")]
+pub use mux::Mux;
+
+#[cfg(feature = "socket")]
+mod queue;
+
 #[cfg(feature = "socket")]
 mod socket;
 #[cfg(feature = "socket")]
-pub use socket::Socket;
+pub use socket::{
+    FixedAddress, FixedError, FixedSocket, FunctionalError, FunctionalSocket, Responder,
+    ResponderSocket, Socket, SocketError,
+};
 
 #[cfg(feature = "transport")]
 pub mod transport;
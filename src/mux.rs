@@ -0,0 +1,382 @@
+//! Multiplexer serving many ISO-TP channels over one CAN controller.
+//!
+//! A [`Mux`] holds a table of channels, each with its own transmit and receive identifier.
+//! [`Mux::run`] drives the CAN controller, routing received frames to the channel they belong to
+//! and transmitting frames from each channel in turn.
+//! Each [`Handle`] is a [`Transport`](crate::Transport) for one channel.
+//!
+//! The table can be a statically sized array, or a `Vec` with the `alloc` feature.
+
+use crate::{frame::Format, queue::Queue, Frame};
+use core::{
+    cell::RefCell,
+    convert::Infallible,
+    future::poll_fn,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+use embedded_hal::can::Id;
+use futures::{Sink, SinkExt, Stream, StreamExt};
+
+#[cfg(feature = "transport")]
+use crate::transport::{Reader, Writer};
+
+/// Error sending a frame on a [`Handle`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SendError {
+    /// The [`Mux`] stopped running.
+    Closed,
+    /// The frame is longer than the CAN frame type of the mux can hold.
+    FrameTooLong,
+    /// The frame wasn't created with the addressing of the channel's format.
+    AddressMismatch,
+}
+
+/// Error that stopped a [`Mux`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error<T, R> {
+    Transmit(T),
+    Receive(R),
+}
+
+/// Channel in the table of a [`Mux`].
+pub struct Channel {
+    tx_id: Id,
+    rx_id: Id,
+    format: Format,
+    rx: Queue,
+    tx: Option<Frame>,
+    tx_error: Option<SendError>,
+    rx_waker: Option<Waker>,
+    tx_waker: Option<Waker>,
+}
+
+impl Channel {
+    /// Create a new channel that transmits frames with `tx_id` and receives frames with `rx_id`.
+    pub fn new(tx_id: impl Into<Id>, rx_id: impl Into<Id>) -> Self {
+        Self::with_format(tx_id, rx_id, Format::default())
+    }
+
+    /// Create a new channel for frames of `format`.
+    ///
+    /// Channels with extended or mixed addressing can share identifiers,
+    /// as received frames are also routed by their address byte.
+    pub fn with_format(tx_id: impl Into<Id>, rx_id: impl Into<Id>, format: Format) -> Self {
+        Self {
+            tx_id: tx_id.into(),
+            rx_id: rx_id.into(),
+            format,
+            rx: Queue::new(),
+            tx: None,
+            tx_error: None,
+            rx_waker: None,
+            tx_waker: None,
+        }
+    }
+
+    /// Returns the identifier of transmitted frames.
+    pub fn tx_id(&self) -> Id {
+        self.tx_id
+    }
+
+    /// Returns the identifier of received frames.
+    pub fn rx_id(&self) -> Id {
+        self.rx_id
+    }
+
+    /// Returns the format of frames on this channel.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Returns the number of received frames dropped because this channel wasn't read in time.
+    pub fn dropped(&self) -> u32 {
+        self.rx.dropped()
+    }
+}
+
+struct Table<S> {
+    channels: S,
+    // Next channel to transmit from
+    next_tx: usize,
+    is_closed: bool,
+    waker: Option<Waker>,
+}
+
+impl<S> Table<S>
+where
+    S: AsMut<[Channel]>,
+{
+    fn close(&mut self) {
+        self.is_closed = true;
+        for channel in self.channels.as_mut() {
+            if let Some(waker) = channel.rx_waker.take() {
+                waker.wake();
+            }
+            if let Some(waker) = channel.tx_waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Multiplexer of ISO-TP channels over one CAN controller.
+pub struct Mux<S> {
+    table: RefCell<Table<S>>,
+}
+
+impl<S> Mux<S>
+where
+    S: AsMut<[Channel]>,
+{
+    /// Create a new multiplexer for a table of `channels`.
+    pub fn new(channels: S) -> Self {
+        Self {
+            table: RefCell::new(Table {
+                channels,
+                next_tx: 0,
+                is_closed: false,
+                waker: None,
+            }),
+        }
+    }
+
+    /// Returns a handle to the channel at `index`.
+    ///
+    /// Only one handle should be used for each channel at a time.
+    ///
+    /// # Panics
+    /// Panics if `index` is out of bounds.
+    pub fn channel(&self, index: usize) -> Handle<'_, S> {
+        assert!(index < self.table.borrow_mut().channels.as_mut().len());
+        Handle { mux: self, index }
+    }
+
+    /// Drive the CAN controller `can` until it stops receiving frames or fails.
+    ///
+    /// Received frames are routed to their channel and frames from other nodes are dropped.
+    /// Each channel queues a few received frames,
+    /// and frames for a channel whose queue is full are dropped without affecting the others.
+    /// Channels take turns transmitting a frame.
+    ///
+    /// Handles are closed once this returns.
+    pub async fn run<C, E, F>(&self, mut can: C) -> Result<(), Error<C::Error, E>>
+    where
+        C: Stream<Item = Result<F, E>> + Sink<F> + Unpin,
+        F: async_hal::can::Frame,
+    {
+        let result = poll_fn(|cx| self.poll_run(cx, &mut can)).await;
+        self.table.borrow_mut().close();
+        result
+    }
+
+    fn poll_run<C, E, F>(
+        &self,
+        cx: &mut Context,
+        can: &mut C,
+    ) -> Poll<Result<(), Error<C::Error, E>>>
+    where
+        C: Stream<Item = Result<F, E>> + Sink<F> + Unpin,
+        F: async_hal::can::Frame,
+    {
+        let mut table = self.table.borrow_mut();
+        table.waker = Some(cx.waker().clone());
+        let Table {
+            channels, next_tx, ..
+        } = &mut *table;
+        let channels = channels.as_mut();
+
+        loop {
+            let can_frame = match can.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(can_frame))) => can_frame,
+                Poll::Ready(Some(Err(error))) => return Poll::Ready(Err(Error::Receive(error))),
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Pending => break,
+            };
+
+            let id = can_frame.id();
+            let routed = channels
+                .iter_mut()
+                .filter(|channel| channel.rx_id == id)
+                .find_map(|channel| Some((channel.format.decode(can_frame.data())?, channel)));
+            if let Some((frame, channel)) = routed {
+                channel.rx.push(frame.with_id(id));
+                if let Some(waker) = channel.rx_waker.take() {
+                    waker.wake();
+                }
+            }
+        }
+
+        while can
+            .poll_ready_unpin(cx)
+            .map_err(Error::Transmit)?
+            .is_ready()
+        {
+            let len = channels.len();
+            let Some(index) = (0..len)
+                .map(|offset| (*next_tx + offset) % len)
+                .find(|&index| channels[index].tx.is_some())
+            else {
+                break;
+            };
+
+            let channel = &mut channels[index];
+            let frame = channel.tx.take().unwrap();
+            match F::new(channel.tx_id, frame.as_ref()) {
+                Some(can_frame) => can.start_send_unpin(can_frame).map_err(Error::Transmit)?,
+                // Fail this frame on its channel instead of stopping the mux
+                None => channel.tx_error = Some(SendError::FrameTooLong),
+            }
+
+            if let Some(waker) = channel.tx_waker.take() {
+                waker.wake();
+            }
+            *next_tx = (index + 1) % len;
+        }
+
+        // The controller wakes this again if transmitted frames are still being flushed
+        let _ = can.poll_flush_unpin(cx).map_err(Error::Transmit)?;
+
+        Poll::Pending
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Mux<alloc::vec::Vec<Channel>> {
+    /// Add a `channel` to the table, returning a handle to it.
+    pub fn push(&self, channel: Channel) -> Handle<'_, alloc::vec::Vec<Channel>> {
+        let mut table = self.table.borrow_mut();
+        table.channels.push(channel);
+        Handle {
+            mux: self,
+            index: table.channels.len() - 1,
+        }
+    }
+}
+
+/// Handle to one channel of a [`Mux`].
+pub struct Handle<'a, S> {
+    mux: &'a Mux<S>,
+    index: usize,
+}
+
+impl<S> Handle<'_, S> {
+    /// Returns the index of this channel in the table.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+impl<S> Handle<'_, S>
+where
+    S: AsMut<[Channel]>,
+{
+    /// Returns the format of frames on this channel.
+    pub fn format(&self) -> Format {
+        self.mux.table.borrow_mut().channels.as_mut()[self.index].format
+    }
+
+    /// Returns the number of received frames dropped because this channel wasn't read in time.
+    pub fn dropped(&self) -> u32 {
+        self.mux.table.borrow_mut().channels.as_mut()[self.index].dropped()
+    }
+
+    /// Create a reader that sends flow control frames in the format of this channel.
+    #[cfg(feature = "transport")]
+    pub fn reader(self) -> Reader<Self, Infallible> {
+        let format = self.format();
+        Reader::with_format(self, format)
+    }
+
+    /// Create a writer that segments messages into frames in the format of this channel.
+    #[cfg(feature = "transport")]
+    pub fn writer<D>(self, delay: D) -> Writer<Self, Infallible, D> {
+        let format = self.format();
+        Writer::with_format(self, delay, format)
+    }
+}
+
+impl<S> Stream for Handle<'_, S>
+where
+    S: AsMut<[Channel]>,
+{
+    type Item = Result<Frame, Infallible>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let mut table = self.mux.table.borrow_mut();
+
+        if let Some(frame) = table.channels.as_mut()[self.index].rx.pop() {
+            return Poll::Ready(Some(Ok(frame)));
+        }
+        if table.is_closed {
+            return Poll::Ready(None);
+        }
+
+        table.channels.as_mut()[self.index].rx_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<S> Handle<'_, S>
+where
+    S: AsMut<[Channel]>,
+{
+    /// Poll until the frame queued on this channel was transmitted.
+    fn poll_sent(&self, cx: &mut Context) -> Poll<Result<(), SendError>> {
+        let mut table = self.mux.table.borrow_mut();
+        let is_closed = table.is_closed;
+        let channel = &mut table.channels.as_mut()[self.index];
+
+        if is_closed {
+            Poll::Ready(Err(SendError::Closed))
+        } else if let Some(error) = channel.tx_error.take() {
+            Poll::Ready(Err(error))
+        } else if channel.tx.is_none() {
+            Poll::Ready(Ok(()))
+        } else {
+            channel.tx_waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl<S> Sink<Frame> for Handle<'_, S>
+where
+    S: AsMut<[Channel]>,
+{
+    type Error = SendError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.poll_sent(cx)
+    }
+
+    /// Queue `item` to be transmitted on this channel.
+    fn start_send(self: Pin<&mut Self>, item: Frame) -> Result<(), Self::Error> {
+        let mut table = self.mux.table.borrow_mut();
+        if table.is_closed {
+            return Err(SendError::Closed);
+        }
+
+        let channel = &mut table.channels.as_mut()[self.index];
+        if !channel.format.is_tx_frame(&item) {
+            return Err(SendError::AddressMismatch);
+        }
+        channel.tx = Some(item);
+        table.wake();
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.poll_sent(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.poll_sent(cx)
+    }
+}
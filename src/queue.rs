@@ -0,0 +1,47 @@
+use crate::Frame;
+
+/// Fixed-size ring of received frames waiting to be read.
+pub(crate) struct Queue {
+    frames: [Option<Frame>; Self::CAPACITY],
+    head: usize,
+    len: usize,
+    dropped: u32,
+}
+
+impl Queue {
+    /// Number of frames held before new frames are dropped.
+    pub(crate) const CAPACITY: usize = 8;
+
+    pub(crate) fn new() -> Self {
+        Self {
+            frames: Default::default(),
+            head: 0,
+            len: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Add `frame` to the end of the queue, or drop it if the queue is full.
+    pub(crate) fn push(&mut self, frame: Frame) {
+        if self.len == Self::CAPACITY {
+            self.dropped = self.dropped.saturating_add(1);
+            return;
+        }
+
+        self.frames[(self.head + self.len) % Self::CAPACITY] = Some(frame);
+        self.len += 1;
+    }
+
+    /// Remove the oldest frame from the queue.
+    pub(crate) fn pop(&mut self) -> Option<Frame> {
+        let frame = self.frames[self.head].take()?;
+        self.head = (self.head + 1) % Self::CAPACITY;
+        self.len -= 1;
+        Some(frame)
+    }
+
+    /// Returns the number of frames dropped because the queue was full.
+    pub(crate) fn dropped(&self) -> u32 {
+        self.dropped
+    }
+}
@@ -1,17 +1,26 @@
-use crate::Frame;
+use crate::{
+    frame::{FlowKind, Format, Kind, Pdu},
+    queue::Queue,
+    Frame,
+};
 use async_hal::can::{CanReceive, CanTransmit, Frame as _};
 use core::{
     marker::PhantomData,
     pin::Pin,
     task::{Context, Poll},
 };
-use embedded_hal::can::Id;
-use futures::{Sink, SinkExt, Stream, StreamExt};
+use embedded_hal::can::{ExtendedId, Id};
+use futures::{ready, Sink, SinkExt, Stream, StreamExt};
 use pin_project_lite::pin_project;
 
+#[cfg(feature = "transport")]
+use crate::transport::{Reader, Writer};
+
 pin_project! {
     pub struct Socket<C, E, F> {
-        id: Id,
+        tx_id: Id,
+        rx_id: Id,
+        format: Format,
 
         #[pin]
         can: C,
@@ -21,13 +30,54 @@ pin_project! {
 }
 
 impl<C, E, F> Socket<C, E, F> {
-    pub fn new(id: impl Into<Id>, can: C) -> Self {
+    /// Create a new socket that transmits frames with `tx_id` and receives frames with `rx_id`.
+    ///
+    /// Received frames with any other identifier are dropped.
+    pub fn new(tx_id: impl Into<Id>, rx_id: impl Into<Id>, can: C) -> Self {
+        Self::with_format(tx_id, rx_id, can, Format::default())
+    }
+
+    /// Create a new socket for frames of `format`.
+    ///
+    /// With extended or mixed addressing, received frames without the expected address byte are dropped.
+    pub fn with_format(tx_id: impl Into<Id>, rx_id: impl Into<Id>, can: C, format: Format) -> Self {
         Self {
-            id: id.into(),
+            tx_id: tx_id.into(),
+            rx_id: rx_id.into(),
+            format,
             can,
             _marker: PhantomData,
         }
     }
+
+    /// Returns the identifier of transmitted frames.
+    pub fn tx_id(&self) -> Id {
+        self.tx_id
+    }
+
+    /// Returns the identifier of received frames.
+    pub fn rx_id(&self) -> Id {
+        self.rx_id
+    }
+
+    /// Returns the format of frames on this socket.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Create a reader that sends flow control frames in the format of this socket.
+    #[cfg(feature = "transport")]
+    pub fn reader(self) -> Reader<Self, E> {
+        let format = self.format;
+        Reader::with_format(self, format)
+    }
+
+    /// Create a writer that segments messages into frames in the format of this socket.
+    #[cfg(feature = "transport")]
+    pub fn writer<D>(self, delay: D) -> Writer<Self, E, D> {
+        let format = self.format;
+        Writer::with_format(self, delay, format)
+    }
 }
 
 impl<C, E, F> Stream for Socket<C, E, F>
@@ -37,34 +87,704 @@ where
     type Item = Result<Frame, E>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let mut me = self.project();
+
+        loop {
+            let can_frame = match ready!(me.can.poll_next_unpin(cx)) {
+                Some(Ok(can_frame)) => can_frame,
+                Some(Err(error)) => return Poll::Ready(Some(Err(error))),
+                None => return Poll::Ready(None),
+            };
+
+            // Drop frames from other nodes
+            if can_frame.id() != *me.rx_id {
+                continue;
+            }
+
+            // Drop frames addressed to other nodes
+            if let Some(frame) = me.format.decode(can_frame.data()) {
+                return Poll::Ready(Some(Ok(frame.with_id(can_frame.id()))));
+            }
+        }
+    }
+}
+
+impl<C, E, F> Sink<Frame> for Socket<C, E, F>
+where
+    C: CanTransmit<F>,
+    F: async_hal::can::Frame,
+{
+    type Error = SocketError<C::Error>;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.project()
+            .can
+            .poll_ready_unpin(cx)
+            .map_err(SocketError::Transmit)
+    }
+
+    /// Transmit `item` with the identifier of this socket.
+    fn start_send(self: Pin<&mut Self>, item: Frame) -> Result<(), Self::Error> {
+        if !self.format.is_tx_frame(&item) {
+            return Err(SocketError::AddressMismatch);
+        }
+        let can_frame = F::new(self.tx_id, item.as_ref()).ok_or(SocketError::FrameTooLong)?;
         self.project()
             .can
-            .poll_next_unpin(cx)
-            .map_ok(|can_frame| Frame::from_bytes(can_frame.data()))
+            .start_send_unpin(can_frame)
+            .map_err(SocketError::Transmit)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.project()
+            .can
+            .poll_flush_unpin(cx)
+            .map_err(SocketError::Transmit)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.project()
+            .can
+            .poll_close_unpin(cx)
+            .map_err(SocketError::Transmit)
     }
 }
 
-impl<C, E, F> Sink<Frame> for Socket<C, E, F>
+/// Error transmitting on a [`Socket`] or [`ResponderSocket`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SocketError<E> {
+    Transmit(E),
+    /// The frame is longer than the CAN frame type can hold,
+    /// such as a CAN FD frame on a classic CAN controller.
+    FrameTooLong,
+    /// The frame wasn't created with the addressing of the socket's format.
+    AddressMismatch,
+}
+
+/// Error transmitting on a [`FunctionalSocket`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FunctionalError<E> {
+    Transmit(E),
+    /// A frame other than a single frame was sent,
+    /// functional requests must fit in a single frame.
+    Segmented,
+    /// The frame wasn't created with the addressing of the socket's format.
+    AddressMismatch,
+    /// The frame is longer than the CAN frame type can hold.
+    FrameTooLong,
+}
+
+/// Node responding to the requests of a [`FunctionalSocket`].
+pub struct Responder {
+    tx_id: Id,
+    rx_id: Id,
+    queue: Queue,
+}
+
+impl Responder {
+    /// Create a responder that receives physical frames with `tx_id` and responds with `rx_id`.
+    pub fn new(tx_id: impl Into<Id>, rx_id: impl Into<Id>) -> Self {
+        Self {
+            tx_id: tx_id.into(),
+            rx_id: rx_id.into(),
+            queue: Queue::new(),
+        }
+    }
+
+    /// Returns the identifier of frames transmitted to this responder.
+    pub fn tx_id(&self) -> Id {
+        self.tx_id
+    }
+
+    /// Returns the identifier of frames received from this responder.
+    pub fn rx_id(&self) -> Id {
+        self.rx_id
+    }
+
+    /// Returns the number of frames from this responder that were dropped
+    /// because too many were waiting to be read.
+    pub fn dropped(&self) -> u32 {
+        self.queue.dropped()
+    }
+}
+
+pin_project! {
+    /// Socket for functional (one-to-many) requests.
+    ///
+    /// Requests are transmitted with a functional identifier such as `0x7DF`,
+    /// and frames from a table of responders are received with the index of their responder.
+    /// Each response can be read on its own with [`FunctionalSocket::responder`],
+    /// which keeps frames from the other responders until they're received.
+    pub struct FunctionalSocket<C, E, F, R> {
+        tx_id: Id,
+        responders: R,
+        format: Format,
+
+        #[pin]
+        can: C,
+        _marker: PhantomData<(E, F)>,
+    }
+}
+
+impl<C, E, F, R> FunctionalSocket<C, E, F, R> {
+    /// Create a new socket that transmits requests with `tx_id`
+    /// and receives responses from `responders`, such as an array of [`Responder`].
+    ///
+    /// Received frames with any other identifier are dropped.
+    pub fn new(tx_id: impl Into<Id>, responders: R, can: C) -> Self {
+        Self::with_format(tx_id, responders, can, Format::default())
+    }
+
+    /// Create a new socket for frames of `format`.
+    pub fn with_format(tx_id: impl Into<Id>, responders: R, can: C, format: Format) -> Self {
+        Self {
+            tx_id: tx_id.into(),
+            responders,
+            format,
+            can,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the functional identifier of transmitted frames.
+    pub fn tx_id(&self) -> Id {
+        self.tx_id
+    }
+
+    /// Returns the table of responders.
+    pub fn responders(&self) -> &[Responder]
+    where
+        R: AsRef<[Responder]>,
+    {
+        self.responders.as_ref()
+    }
+
+    /// Returns the format of frames on this socket.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Borrow a physical socket to the responder at `index`.
+    ///
+    /// A segmented response can be read by pushing its first frame into a reader of this socket.
+    ///
+    /// # Panics
+    /// Panics if `index` is out of bounds.
+    pub fn responder(&mut self, index: usize) -> ResponderSocket<'_, C, E, F, R>
+    where
+        R: AsRef<[Responder]>,
+    {
+        assert!(index < self.responders.as_ref().len());
+        ResponderSocket {
+            socket: self,
+            index,
+        }
+    }
+
+    /// Returns the underlying CAN driver.
+    pub fn into_inner(self) -> C {
+        self.can
+    }
+}
+
+impl<C, E, F, R> FunctionalSocket<C, E, F, R>
+where
+    C: CanReceive<Error = E> + Unpin,
+    R: AsMut<[Responder]>,
+{
+    /// Poll the next frame from the responder at `index`, or from any responder,
+    /// keeping frames from the other responders in their queue.
+    fn poll_responder(
+        &mut self,
+        cx: &mut Context,
+        index: Option<usize>,
+    ) -> Poll<Option<Result<(usize, Frame), E>>> {
+        let responders = self.responders.as_mut();
+
+        // Frames that were already received come first
+        let queued = match index {
+            Some(index) => responders[index].queue.pop().map(|frame| (index, frame)),
+            None => responders
+                .iter_mut()
+                .enumerate()
+                .find_map(|(index, responder)| Some((index, responder.queue.pop()?))),
+        };
+        if let Some(queued) = queued {
+            return Poll::Ready(Some(Ok(queued)));
+        }
+
+        loop {
+            let can_frame = match ready!(self.can.poll_next_unpin(cx)) {
+                Some(Ok(can_frame)) => can_frame,
+                Some(Err(error)) => return Poll::Ready(Some(Err(error))),
+                None => return Poll::Ready(None),
+            };
+
+            // Drop frames from nodes that don't respond to this request
+            let id = can_frame.id();
+            let Some(received) = responders
+                .iter()
+                .position(|responder| responder.rx_id == id)
+            else {
+                continue;
+            };
+            let Some(frame) = self.format.decode(can_frame.data()) else {
+                continue;
+            };
+
+            let frame = frame.with_id(id);
+            if index.is_none_or(|index| index == received) {
+                return Poll::Ready(Some(Ok((received, frame))));
+            }
+            responders[received].queue.push(frame);
+        }
+    }
+}
+
+impl<C, E, F, R> Stream for FunctionalSocket<C, E, F, R>
+where
+    C: CanReceive<Error = E> + Unpin,
+    R: AsMut<[Responder]>,
+{
+    type Item = Result<(usize, Frame), E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_responder(cx, None)
+    }
+}
+
+impl<C, E, F, R> Sink<Frame> for FunctionalSocket<C, E, F, R>
+where
+    C: CanTransmit<F>,
+    F: async_hal::can::Frame,
+{
+    type Error = FunctionalError<C::Error>;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.project()
+            .can
+            .poll_ready_unpin(cx)
+            .map_err(FunctionalError::Transmit)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Frame) -> Result<(), Self::Error> {
+        if !self.format.is_tx_frame(&item) {
+            return Err(FunctionalError::AddressMismatch);
+        }
+        if item.kind() != Some(Kind::Single) {
+            return Err(FunctionalError::Segmented);
+        }
+
+        let can_frame = F::new(self.tx_id, item.as_ref()).ok_or(FunctionalError::FrameTooLong)?;
+        self.project()
+            .can
+            .start_send_unpin(can_frame)
+            .map_err(FunctionalError::Transmit)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.project()
+            .can
+            .poll_flush_unpin(cx)
+            .map_err(FunctionalError::Transmit)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.project()
+            .can
+            .poll_close_unpin(cx)
+            .map_err(FunctionalError::Transmit)
+    }
+}
+
+/// Physical socket to one responder of a [`FunctionalSocket`].
+pub struct ResponderSocket<'a, C, E, F, R> {
+    socket: &'a mut FunctionalSocket<C, E, F, R>,
+    index: usize,
+}
+
+impl<C, E, F, R> ResponderSocket<'_, C, E, F, R> {
+    /// Returns the index of this responder.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Create a reader that sends flow control frames in the format of this socket.
+    #[cfg(feature = "transport")]
+    pub fn reader(self) -> Reader<Self, E> {
+        let format = self.socket.format;
+        Reader::with_format(self, format)
+    }
+
+    /// Create a writer that segments messages into frames in the format of this socket.
+    #[cfg(feature = "transport")]
+    pub fn writer<D>(self, delay: D) -> Writer<Self, E, D> {
+        let format = self.socket.format;
+        Writer::with_format(self, delay, format)
+    }
+}
+
+impl<C, E, F, R> Stream for ResponderSocket<'_, C, E, F, R>
+where
+    C: CanReceive<Error = E> + Unpin,
+    R: AsMut<[Responder]>,
+{
+    type Item = Result<Frame, E>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let index = self.index;
+        self.socket
+            .poll_responder(cx, Some(index))
+            .map(|item| item.map(|result| result.map(|(_, frame)| frame)))
+    }
+}
+
+impl<C, E, F, R> Sink<Frame> for ResponderSocket<'_, C, E, F, R>
+where
+    C: CanTransmit<F> + Unpin,
+    F: async_hal::can::Frame,
+    R: AsRef<[Responder]>,
+{
+    type Error = SocketError<C::Error>;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.socket
+            .can
+            .poll_ready_unpin(cx)
+            .map_err(SocketError::Transmit)
+    }
+
+    /// Transmit `item` to this responder.
+    fn start_send(mut self: Pin<&mut Self>, item: Frame) -> Result<(), Self::Error> {
+        if !self.socket.format.is_tx_frame(&item) {
+            return Err(SocketError::AddressMismatch);
+        }
+        let tx_id = self.socket.responders.as_ref()[self.index].tx_id;
+        let can_frame = F::new(tx_id, item.as_ref()).ok_or(SocketError::FrameTooLong)?;
+        self.socket
+            .can
+            .start_send_unpin(can_frame)
+            .map_err(SocketError::Transmit)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.socket
+            .can
+            .poll_flush_unpin(cx)
+            .map_err(SocketError::Transmit)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.socket
+            .can
+            .poll_close_unpin(cx)
+            .map_err(SocketError::Transmit)
+    }
+}
+
+/// Normal fixed addressing, with the target (N_TA) and source (N_SA) address
+/// encoded in a 29-bit identifier.
+///
+/// Physical addresses use identifiers `0x18DA_TA_SA` and functional addresses `0x18DB_TA_SA`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FixedAddress {
+    target: u8,
+    source: u8,
+    is_functional: bool,
+}
+
+impl FixedAddress {
+    const PHYSICAL: u32 = 0x18DA_0000;
+    const FUNCTIONAL: u32 = 0x18DB_0000;
+
+    /// Create a physical (one-to-one) address from `source` to `target`.
+    pub const fn physical(target: u8, source: u8) -> Self {
+        Self {
+            target,
+            source,
+            is_functional: false,
+        }
+    }
+
+    /// Create a functional (one-to-many) address from `source` to `target`.
+    pub const fn functional(target: u8, source: u8) -> Self {
+        Self {
+            target,
+            source,
+            is_functional: true,
+        }
+    }
+
+    /// Parse the address of a frame received with `id`,
+    /// or `None` if it doesn't use normal fixed addressing.
+    ///
+    /// The priority bits of the identifier are ignored.
+    pub fn from_id(id: impl Into<Id>) -> Option<Self> {
+        let Id::Extended(id) = id.into() else {
+            return None;
+        };
+        let raw = id.as_raw();
+        let target = (raw >> 8) as u8;
+        let source = raw as u8;
+
+        match raw & 0x03FF_0000 {
+            0x00DA_0000 => Some(Self::physical(target, source)),
+            0x00DB_0000 => Some(Self::functional(target, source)),
+            _ => None,
+        }
+    }
+
+    /// Returns the identifier of frames sent to this address, with the default priority of 6.
+    pub fn to_id(&self) -> ExtendedId {
+        let base = if self.is_functional {
+            Self::FUNCTIONAL
+        } else {
+            Self::PHYSICAL
+        };
+        let raw = base | (self.target as u32) << 8 | self.source as u32;
+        ExtendedId::new(raw).unwrap()
+    }
+
+    /// Returns the target address (N_TA).
+    pub fn target(&self) -> u8 {
+        self.target
+    }
+
+    /// Returns the source address (N_SA).
+    pub fn source(&self) -> u8 {
+        self.source
+    }
+
+    /// Returns `true` if this is a functional address.
+    pub fn is_functional(&self) -> bool {
+        self.is_functional
+    }
+
+    /// Returns the physical address from `address` to the source of this address.
+    pub fn reply(&self, address: u8) -> Self {
+        Self::physical(self.source, address)
+    }
+}
+
+impl From<FixedAddress> for ExtendedId {
+    fn from(address: FixedAddress) -> Self {
+        address.to_id()
+    }
+}
+
+impl From<FixedAddress> for Id {
+    fn from(address: FixedAddress) -> Self {
+        Id::Extended(address.to_id())
+    }
+}
+
+/// Error transmitting on a [`FixedSocket`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FixedError<E> {
+    Transmit(E),
+    /// No frame was received and no peer was set with [`FixedSocket::connect`].
+    NoPeer,
+    /// The frame wasn't created with the addressing of the socket's format.
+    AddressMismatch,
+    /// The frame is longer than the CAN frame type can hold.
+    FrameTooLong,
+}
+
+pin_project! {
+    /// Socket using normal fixed addressing for the node with `address`.
+    ///
+    /// Frames addressed to this node are received from any source,
+    /// and frames are transmitted to the source of the last received frame.
+    /// While a segmented message is exchanged with a peer, frames from other sources are dropped.
+    ///
+    /// The identifier of each received frame is kept with [`Frame::id`],
+    /// so the peer of a message is also available from its header.
+    pub struct FixedSocket<C, E, F> {
+        address: u8,
+        functional_address: Option<u8>,
+        peer: Option<FixedAddress>,
+        // Bytes left of the segmented message exchanged with the peer
+        remaining: u32,
+        format: Format,
+
+        #[pin]
+        can: C,
+        _marker: PhantomData<(E, F)>,
+    }
+}
+
+impl<C, E, F> FixedSocket<C, E, F> {
+    /// Create a new socket for the node with `address`.
+    ///
+    /// Received frames addressed to any other node are dropped.
+    pub fn new(address: u8, can: C) -> Self {
+        Self::with_format(address, can, Format::default())
+    }
+
+    /// Create a new socket for frames of `format`.
+    pub fn with_format(address: u8, can: C, format: Format) -> Self {
+        Self {
+            address,
+            functional_address: None,
+            peer: None,
+            remaining: 0,
+            format,
+            can,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the address (N_SA) of this node.
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    /// Also receive frames sent to the functional `address`, such as `0x33` for OBD requests.
+    pub fn with_functional_address(mut self, address: u8) -> Self {
+        self.functional_address = Some(address);
+        self
+    }
+
+    /// Returns the functional address of this node, if any.
+    pub fn functional_address(&self) -> Option<u8> {
+        self.functional_address
+    }
+
+    /// Returns the address of the last received frame, if any.
+    ///
+    /// Its source is the peer that frames are transmitted to.
+    pub fn peer(&self) -> Option<FixedAddress> {
+        self.peer
+    }
+
+    /// Transmit frames to the node with `target` address, until a frame from another node is received.
+    pub fn connect(&mut self, target: u8) {
+        self.peer = Some(FixedAddress::physical(self.address, target));
+        self.remaining = 0;
+    }
+
+    /// Stop waiting for the rest of a segmented message,
+    /// receiving frames from any source again.
+    ///
+    /// Call this once a message to or from the peer failed, such as after a timeout.
+    pub fn release(&mut self) {
+        self.remaining = 0;
+    }
+
+    /// Returns the format of frames on this socket.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Create a reader that sends flow control frames in the format of this socket.
+    #[cfg(feature = "transport")]
+    pub fn reader(self) -> Reader<Self, E> {
+        let format = self.format;
+        Reader::with_format(self, format)
+    }
+
+    /// Create a writer that segments messages into frames in the format of this socket.
+    #[cfg(feature = "transport")]
+    pub fn writer<D>(self, delay: D) -> Writer<Self, E, D> {
+        let format = self.format;
+        Writer::with_format(self, delay, format)
+    }
+}
+
+/// Returns the bytes left of a segmented message after `frame`, with `remaining` bytes left before it.
+fn remaining_after(remaining: u32, frame: &Frame) -> u32 {
+    match frame.parse() {
+        Ok(Pdu::First { len, data }) => len.saturating_sub(data.len() as u32),
+        Ok(Pdu::Consecutive { data, .. }) => remaining.saturating_sub(data.len() as u32),
+        Ok(Pdu::Flow { kind, .. }) if kind != FlowKind::Overflow => remaining,
+        _ => 0,
+    }
+}
+
+impl<C, E, F> Stream for FixedSocket<C, E, F>
+where
+    C: CanReceive<Error = E>,
+{
+    type Item = Result<Frame, E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let mut me = self.project();
+
+        loop {
+            let can_frame = match ready!(me.can.poll_next_unpin(cx)) {
+                Some(Ok(can_frame)) => can_frame,
+                Some(Err(error)) => return Poll::Ready(Some(Err(error))),
+                None => return Poll::Ready(None),
+            };
+
+            // Drop frames addressed to other nodes
+            let peer = match FixedAddress::from_id(can_frame.id()) {
+                Some(peer) if peer.is_functional && Some(peer.target) == *me.functional_address => {
+                    peer
+                }
+                Some(peer) if !peer.is_functional && peer.target == *me.address => peer,
+                _ => continue,
+            };
+
+            let Some(frame) = me.format.decode(can_frame.data()) else {
+                continue;
+            };
+
+            // Drop frames from other nodes until the segmented message with the peer is done
+            let is_peer = me.peer.map(|current| current.source) == Some(peer.source);
+            if *me.remaining > 0 && !is_peer {
+                continue;
+            }
+
+            *me.peer = Some(peer);
+            *me.remaining = remaining_after(*me.remaining, &frame);
+            return Poll::Ready(Some(Ok(frame.with_id(can_frame.id()))));
+        }
+    }
+}
+
+impl<C, E, F> Sink<Frame> for FixedSocket<C, E, F>
 where
     C: CanTransmit<F>,
     F: async_hal::can::Frame,
 {
-    type Error = C::Error;
+    type Error = FixedError<C::Error>;
 
     fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
-        self.project().can.poll_ready_unpin(cx)
+        self.project()
+            .can
+            .poll_ready_unpin(cx)
+            .map_err(FixedError::Transmit)
     }
 
+    /// Transmit `item` to the current peer.
     fn start_send(self: Pin<&mut Self>, item: Frame) -> Result<(), Self::Error> {
-        let can_frame = F::new(self.id, item.as_ref()).unwrap();
-        self.project().can.start_send_unpin(can_frame)
+        if !self.format.is_tx_frame(&item) {
+            return Err(FixedError::AddressMismatch);
+        }
+        let mut me = self.project();
+        let peer = me.peer.ok_or(FixedError::NoPeer)?;
+
+        let can_frame =
+            F::new(peer.reply(*me.address), item.as_ref()).ok_or(FixedError::FrameTooLong)?;
+        me.can
+            .start_send_unpin(can_frame)
+            .map_err(FixedError::Transmit)?;
+        *me.remaining = remaining_after(*me.remaining, &item);
+        Ok(())
     }
 
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
-        self.project().can.poll_flush_unpin(cx)
+        self.project()
+            .can
+            .poll_flush_unpin(cx)
+            .map_err(FixedError::Transmit)
     }
 
     fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
-        self.project().can.poll_close_unpin(cx)
+        self.project()
+            .can
+            .poll_close_unpin(cx)
+            .map_err(FixedError::Transmit)
     }
 }
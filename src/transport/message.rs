@@ -0,0 +1,197 @@
+use super::{reader, writer, Reader, Timer, Writer};
+use crate::Frame;
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use futures::{ready, Sink, Stream};
+use pin_project_lite::pin_project;
+
+/// Buffer for a whole message received from a [`MessageStream`].
+pub trait Message: AsMut<[u8]> + Sized {
+    /// The maximum length of this message, if any.
+    const MAX_LEN: Option<u32> = None;
+
+    /// Create a zeroed message of `len` bytes, up to [`Message::MAX_LEN`].
+    fn zeroed(len: usize) -> Self;
+}
+
+#[cfg(feature = "alloc")]
+impl Message for alloc::vec::Vec<u8> {
+    fn zeroed(len: usize) -> Self {
+        alloc::vec![0; len]
+    }
+}
+
+#[cfg(feature = "heapless")]
+impl<const N: usize> Message for heapless::Vec<u8, N> {
+    const MAX_LEN: Option<u32> = Some(if N > u32::MAX as usize {
+        u32::MAX
+    } else {
+        N as u32
+    });
+
+    fn zeroed(len: usize) -> Self {
+        let mut message = Self::new();
+        message
+            .resize(len, 0)
+            .expect("message length is limited to `MAX_LEN`");
+        message
+    }
+}
+
+/// Maximum length of messages received into a [`Message`] without a [`Message::MAX_LEN`],
+/// unless the reader configures its own [`max_message_len`](reader::Config::max_message_len).
+pub const DEFAULT_MAX_LEN: u32 = 4095;
+
+pin_project! {
+    /// Stream of whole messages received by a [`Reader`].
+    ///
+    /// Messages are limited to the reader's maximum message length and [`Message::MAX_LEN`],
+    /// or to [`DEFAULT_MAX_LEN`] if neither is set.
+    /// Longer messages are rejected with an overflow flow control frame before any allocation.
+    pub struct MessageStream<T, E, D, M> {
+        #[pin]
+        reader: Reader<T, E, D>,
+        message: Option<(M, usize)>,
+    }
+}
+
+impl<T, E, D, M> MessageStream<T, E, D, M>
+where
+    T: Sink<Frame> + Stream<Item = Result<Frame, E>>,
+    D: Timer + Unpin,
+{
+    /// Create a new stream of messages from `reader`,
+    /// discarding any message it's reading.
+    pub fn new(mut reader: Reader<T, E, D>) -> Self {
+        reader.reset();
+
+        Self {
+            reader,
+            message: None,
+        }
+    }
+}
+
+impl<T, E, D, M> MessageStream<T, E, D, M> {
+    /// Returns the underlying reader.
+    pub fn into_inner(self) -> Reader<T, E, D> {
+        self.reader
+    }
+}
+
+impl<T, E, D, M> Stream for MessageStream<T, E, D, M>
+where
+    T: Sink<Frame> + Stream<Item = Result<Frame, E>>,
+    D: Timer + Unpin,
+    M: Message,
+{
+    type Item = Result<M, reader::Error<T::Error, E, D::Error>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let mut me = self.project();
+        let max_len = match (me.reader.config().max_message_len(), M::MAX_LEN) {
+            (Some(max_len), Some(message_len)) => max_len.min(message_len),
+            (max_len, message_len) => max_len.or(message_len).unwrap_or(DEFAULT_MAX_LEN),
+        };
+
+        loop {
+            if let Some((message, used)) = me.message {
+                let poll = me.reader.as_mut().poll_message_data(
+                    cx,
+                    &mut message.as_mut()[*used..],
+                    Some(max_len),
+                );
+                let (n, is_finished) = match ready!(poll) {
+                    Ok(output) => output,
+                    Err(error) => {
+                        *me.message = None;
+                        return Poll::Ready(Some(Err(error)));
+                    }
+                };
+
+                *used += n;
+                if is_finished {
+                    let (message, _) = me.message.take().unwrap();
+                    return Poll::Ready(Some(Ok(message)));
+                }
+            } else {
+                let len = match ready!(me.reader.as_mut().poll_message_header(cx, Some(max_len))) {
+                    Ok(header) => header.len(),
+                    // The transport ended between messages
+                    Err(reader::Error::UnexpectedEOF) => return Poll::Ready(None),
+                    Err(error) => return Poll::Ready(Some(Err(error))),
+                };
+
+                *me.message = Some((M::zeroed(len as usize), 0));
+            }
+        }
+    }
+}
+
+pin_project! {
+    /// Sink of whole messages sent by a [`Writer`].
+    pub struct MessageSink<T, E, D, M> {
+        #[pin]
+        writer: Writer<T, E, D>,
+        message: Option<M>,
+        pos: usize,
+    }
+}
+
+impl<T, E, D, M> MessageSink<T, E, D, M> {
+    /// Create a new sink of messages from `writer`.
+    pub fn new(writer: Writer<T, E, D>) -> Self {
+        Self {
+            writer,
+            message: None,
+            pos: 0,
+        }
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> Writer<T, E, D> {
+        self.writer
+    }
+}
+
+impl<T, E, D, M> Sink<M> for MessageSink<T, E, D, M>
+where
+    T: Sink<Frame> + Stream<Item = Result<Frame, E>>,
+    D: Timer + Unpin,
+    M: AsRef<[u8]>,
+{
+    type Error = writer::Error<T::Error, E, D::Error>;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        let me = self.project();
+
+        // Finish sending the current message
+        if let Some(message) = me.message {
+            let poll = me.writer.poll_write_message(cx, message.as_ref(), me.pos);
+            let result = ready!(poll);
+            *me.message = None;
+            result?;
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: M) -> Result<(), Self::Error> {
+        let me = self.project();
+        *me.message = Some(item);
+        *me.pos = 0;
+        Ok(())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        ready!(self.as_mut().poll_ready(cx))?;
+        async_hal::io::AsyncWrite::poll_flush(self.project().writer, cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        ready!(self.as_mut().poll_ready(cx))?;
+        self.project().writer.poll_close(cx)
+    }
+}
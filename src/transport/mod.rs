@@ -1,10 +1,16 @@
 use crate::Frame;
-use async_hal::delay::DelayMs;
+use core::future::Future;
 use futures::{Sink, Stream};
 
+pub mod message;
+pub use message::{Message, MessageSink, MessageStream};
+
 pub mod reader;
 pub use reader::Reader;
 
+pub mod timer;
+pub use timer::Timer;
+
 pub mod writer;
 pub use writer::Writer;
 
@@ -15,11 +21,58 @@ pub trait Transport<E>: Sink<Frame> + Stream<Item = Result<Frame, E>> + Sized {
 
     fn writer<D>(self, delay: D) -> Writer<Self, E, D>
     where
-        D: DelayMs + Unpin,
-        D::Delay: From<u8>,
+        D: Timer + Unpin,
     {
         Writer::new(self, delay)
     }
+
+    /// Send a whole message with `config`, using `delay` for timeouts
+    /// and the separation time between frames.
+    ///
+    /// Empty messages aren't sent.
+    fn send_message<D>(
+        &mut self,
+        delay: D,
+        config: writer::Config,
+        message: &[u8],
+    ) -> impl Future<Output = Result<(), writer::Error<Self::Error, E, D::Error>>>
+    where
+        Self: Unpin,
+        D: Timer + Unpin,
+    {
+        async move {
+            Writer::with_config(self, delay, config)
+                .write_message(message)
+                .await
+        }
+    }
+
+    /// Receive a whole message into `buf` with `config`, using `timer` for timeouts,
+    /// and return its length.
+    ///
+    /// A message that interrupts the current one is received instead of it.
+    /// Messages longer than `buf` are rejected with [`reader::Error::Overflow`].
+    fn recv_message<D>(
+        &mut self,
+        timer: D,
+        config: reader::Config,
+        buf: &mut [u8],
+    ) -> impl Future<Output = Result<usize, reader::Error<Self::Error, E, D::Error>>>
+    where
+        Self: Unpin,
+        D: Timer + Unpin,
+    {
+        async move {
+            let mut reader = Reader::with_timer(self, timer, config);
+            loop {
+                match reader.read_message(buf).await {
+                    // The interrupting message is kept by the reader and read next
+                    Err(reader::Error::Interrupted) => continue,
+                    result => break result,
+                }
+            }
+        }
+    }
 }
 
 impl<T, E> Transport<E> for T where T: Sink<Frame> + Stream<Item = Result<Frame, E>> {}
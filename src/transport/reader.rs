@@ -1,13 +1,18 @@
+use super::timer::{Deadline, Expired, NoTimer, TimeoutKind, Timer};
 use crate::{
-    frame::{FlowKind, Kind},
+    frame::{FlowKind, Format, ParseError, Pdu, SeparationTime, CAN_FD_DL},
     Frame,
 };
 use async_hal::io::AsyncRead;
 use core::{
+    convert::Infallible,
+    future::{poll_fn, Future},
     marker::PhantomData,
-    pin::Pin,
+    pin::{pin, Pin},
     task::{Context, Poll},
+    time::Duration,
 };
+use embedded_hal::can::Id;
 use futures::{ready, Sink, SinkExt, Stream, StreamExt};
 use pin_project_lite::pin_project;
 
@@ -16,31 +21,338 @@ enum State {
     Single {
         frame: Option<Frame>,
     },
+    First {
+        frame: Frame,
+        // Wait frames sent by `poll_hold` before the message is read
+        waits: u8,
+        is_waiting: bool,
+    },
+    Overflow {
+        len: u32,
+        is_flushing: bool,
+    },
     Consecutive {
-        remaining_bytes: u16,
-        remaining_frames: u8,
+        remaining_bytes: u32,
+        remaining_frames: Option<u8>,
         index: u8,
+        waits: u8,
         is_flushing: bool,
+        is_waiting: bool,
     },
 }
 
+impl State {
+    /// Returns `true` once the whole message has been read.
+    fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            Self::Single { frame: None }
+                | Self::Consecutive {
+                    remaining_bytes: 0,
+                    ..
+                }
+        )
+    }
+}
+
+/// Data of the last received frame that hasn't been read yet.
+struct Unread {
+    bytes: [u8; CAN_FD_DL],
+    pos: u8,
+    len: u8,
+}
+
+impl Unread {
+    const EMPTY: Self = Self {
+        bytes: [0; CAN_FD_DL],
+        pos: 0,
+        len: 0,
+    };
+
+    fn new(data: &[u8]) -> Self {
+        let mut bytes = [0; CAN_FD_DL];
+        bytes[..data.len()].copy_from_slice(data);
+        Self {
+            bytes,
+            pos: 0,
+            len: data.len() as u8,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos == self.len
+    }
+
+    /// Copy as much unread data as fits into `buf`, returning the number of bytes read.
+    fn read(&mut self, buf: &mut [u8]) -> usize {
+        let data = &self.bytes[self.pos as usize..self.len as usize];
+        let used = data.len().min(buf.len());
+        buf[..used].copy_from_slice(&data[..used]);
+
+        self.pos += used as u8;
+        used
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub enum Error<T, R> {
+pub enum Error<T, R, D = Infallible> {
     Transmit(T),
     Receive(R),
+    Timer(D),
+    Parse(ParseError),
     InvalidFrame,
-    UnknownFrameKind,
     UnexpectedEOF,
+    /// A consecutive frame was received out of order and the message was aborted.
+    WrongSequenceNumber {
+        expected: u8,
+        received: u8,
+    },
+    /// A message of `len` bytes was announced, larger than the reader accepts.
+    Overflow {
+        len: u32,
+    },
+    /// A new message started before the current one was complete.
+    /// The current message was aborted and the new one is read next.
+    Interrupted,
+    /// The consumer held off the sender for more wait frames than the configured N_WFTmax.
+    WaitLimitExceeded,
+    /// The sender can't be held off without a timer to send wait frames every N_Br,
+    /// see [`Reader::with_timer`].
+    MissingTimer,
+    /// The sender didn't respond in time and the message was aborted.
+    Timeout(TimeoutKind),
+}
+
+impl<T, R, D> From<Expired<D>> for Error<T, R, D> {
+    fn from(expired: Expired<D>) -> Self {
+        match expired {
+            Expired::Timeout(kind) => Self::Timeout(kind),
+            Expired::Timer(error) => Self::Timer(error),
+        }
+    }
+}
+
+/// Kind of a received message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageKind {
+    /// A message sent in a single frame.
+    Single,
+    /// A message sent in a first frame followed by consecutive frames.
+    Segmented,
+}
+
+/// Header of a received message, announced by its single or first frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    len: u32,
+    kind: MessageKind,
+    address: Option<u8>,
+    id: Option<Id>,
+}
+
+impl Header {
+    /// Returns the total length of the message.
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    /// Returns `true` if the message has no data.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the kind of the message.
+    pub fn kind(&self) -> MessageKind {
+        self.kind
+    }
+
+    /// Returns the address byte of the sender if it uses extended or mixed addressing.
+    pub fn address(&self) -> Option<u8> {
+        self.address
+    }
+
+    /// Returns the CAN identifier of the sender, if the transport provides it.
+    ///
+    /// Sockets set this on each received frame, see [`Frame::id`].
+    pub fn id(&self) -> Option<Id> {
+        self.id
+    }
+}
+
+/// Configuration of a [`Reader`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    format: Format,
+    block_len: u8,
+    st: SeparationTime,
+    max_message_len: Option<u32>,
+    n_ar: Option<Duration>,
+    n_cr: Duration,
+    n_br: Duration,
+    n_wft_max: Option<u8>,
+    strict_padding: bool,
+}
+
+impl Config {
+    /// Create a builder for a new configuration.
+    pub fn builder() -> Builder {
+        Builder {
+            config: Self::default(),
+        }
+    }
+
+    /// Returns the format of sent flow control frames.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Returns the block size (BS) sent in flow control frames.
+    pub fn block_len(&self) -> u8 {
+        self.block_len
+    }
+
+    /// Returns the minimum separation time (STmin) sent in flow control frames.
+    pub fn st(&self) -> SeparationTime {
+        self.st
+    }
+
+    /// Returns the maximum length of accepted messages, if any.
+    pub fn max_message_len(&self) -> Option<u32> {
+        self.max_message_len
+    }
+
+    /// Returns the timeout for the transport to send a flow control frame (N_Ar), if any.
+    pub fn n_ar(&self) -> Option<Duration> {
+        self.n_ar
+    }
+
+    /// Returns the timeout for receiving the next consecutive frame (N_Cr).
+    pub fn n_cr(&self) -> Duration {
+        self.n_cr
+    }
+
+    /// Returns the interval between wait flow control frames (N_Br).
+    pub fn n_br(&self) -> Duration {
+        self.n_br
+    }
+
+    /// Returns the maximum number of wait frames sent in a row (N_WFTmax), if any.
+    pub fn n_wft_max(&self) -> Option<u8> {
+        self.n_wft_max
+    }
+
+    /// Returns `true` if received frames must be padded like the configured format.
+    pub fn strict_padding(&self) -> bool {
+        self.strict_padding
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            format: Format::default(),
+            block_len: 10,
+            st: SeparationTime::ZERO,
+            max_message_len: None,
+            n_ar: None,
+            n_cr: Duration::from_millis(1000),
+            n_br: Duration::from_millis(500),
+            n_wft_max: None,
+            strict_padding: false,
+        }
+    }
+}
+
+/// Builder for a reader [`Config`].
+#[derive(Clone, Copy, Debug)]
+pub struct Builder {
+    config: Config,
+}
+
+impl Builder {
+    /// Send flow control frames of `format`.
+    pub fn format(mut self, format: Format) -> Self {
+        self.config.format = format;
+        self
+    }
+
+    /// Set the number of consecutive frames the sender may send before waiting for flow control.
+    /// A block size of zero lets the sender send the whole message without further flow control.
+    pub fn block_len(mut self, block_len: u8) -> Self {
+        self.config.block_len = block_len;
+        self
+    }
+
+    /// Set the minimum separation time (STmin) between consecutive frames.
+    ///
+    /// Times under a millisecond are rounded up to the next 100µs,
+    /// and times over 127ms are limited to 127ms.
+    pub fn st(mut self, st: impl Into<SeparationTime>) -> Self {
+        self.config.st = st.into();
+        self
+    }
+
+    /// Set the maximum length of accepted messages.
+    ///
+    /// Larger segmented messages are rejected by sending an overflow flow control frame,
+    /// and reading them returns [`Error::Overflow`] without receiving any more of their frames.
+    pub fn max_message_len(mut self, max_message_len: u32) -> Self {
+        self.config.max_message_len = Some(max_message_len);
+        self
+    }
+
+    /// Set the timeout for the transport to send a flow control frame (N_Ar).
+    pub fn n_ar(mut self, n_ar: Duration) -> Self {
+        self.config.n_ar = Some(n_ar);
+        self
+    }
+
+    /// Set the timeout for receiving the next consecutive frame (N_Cr).
+    pub fn n_cr(mut self, n_cr: Duration) -> Self {
+        self.config.n_cr = n_cr;
+        self
+    }
+
+    /// Set the interval between wait flow control frames (N_Br).
+    ///
+    /// This should be shorter than the sender's N_Bs timeout.
+    pub fn n_br(mut self, n_br: Duration) -> Self {
+        self.config.n_br = n_br;
+        self
+    }
+
+    /// Set the maximum number of wait frames sent in a row (N_WFTmax).
+    pub fn n_wft_max(mut self, n_wft_max: u8) -> Self {
+        self.config.n_wft_max = Some(n_wft_max);
+        self
+    }
+
+    /// Reject received frames that aren't padded like the configured format
+    /// with [`ParseError::InvalidPadding`].
+    pub fn strict_padding(mut self, strict_padding: bool) -> Self {
+        self.config.strict_padding = strict_padding;
+        self
+    }
+
+    /// Build the reader configuration.
+    pub fn build(self) -> Config {
+        self.config
+    }
 }
 
 pin_project! {
-    pub struct Reader<T, E> {
+    pub struct Reader<T, E, D = NoTimer> {
         #[pin]
         transport: T,
+        #[pin]
+        timer: D,
 
         state: State,
-        block_len: u8,
-        st: u8,
+        unread: Unread,
+        // First frame of a message that interrupted the previous one
+        pending: Option<Frame>,
+        config: Config,
+        deadline: Deadline,
         _marker: PhantomData<E>
     }
 }
@@ -48,116 +360,634 @@ pin_project! {
 impl<T, E> Reader<T, E> {
     /// Create a new reader from a socket.
     pub fn new(transport: T) -> Self {
+        Self::with_config(transport, Config::default())
+    }
+
+    /// Create a new reader that sends flow control frames of `format`.
+    pub fn with_format(transport: T, format: Format) -> Self {
+        Self::with_config(transport, Config::builder().format(format).build())
+    }
+
+    /// Create a new reader from a socket with the given configuration.
+    ///
+    /// Timeouts are disabled, see [`Reader::with_timer`].
+    pub fn with_config(transport: T, config: Config) -> Self {
+        Reader::with_timer(transport, NoTimer, config)
+    }
+}
+
+impl<T, E, D> Reader<T, E, D> {
+    /// Create a new reader from a socket with the given configuration,
+    /// using `timer` for timeouts.
+    pub fn with_timer(transport: T, timer: D, config: Config) -> Self {
         Self {
             transport,
+            timer,
             state: State::Empty,
-            block_len: 10,
-            st: 0,
+            unread: Unread::EMPTY,
+            pending: None,
+            config,
+            deadline: Deadline::default(),
             _marker: PhantomData,
         }
     }
 
-    /// Abort the current read.
+    /// Returns the configuration of this reader.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Returns a reference to the underlying transport.
+    pub fn get_ref(&self) -> &T {
+        &self.transport
+    }
+
+    /// Returns a mutable reference to the underlying transport.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Start the next message from a single or first `frame` that was already received,
+    /// such as a response collected by a `FunctionalSocket`.
+    ///
+    /// The frame is read once any message in progress is finished or reset.
+    pub fn push_frame(&mut self, frame: Frame) {
+        self.pending = Some(frame);
+    }
+
+    /// Abort the current read by sending an overflow flow control frame.
     pub async fn abort(mut self) -> Result<(), T::Error>
     where
         T: Sink<Frame> + Unpin,
     {
-        let frame = Frame::flow(FlowKind::Abort, 0, 0);
+        let frame = self.config.format.flow(FlowKind::Overflow, 0, 0);
         self.transport.send(frame).await
     }
 }
 
-impl<T, E> AsyncRead for Reader<T, E>
+impl<T, E, D> Reader<T, E, D>
 where
     T: Sink<Frame> + Stream<Item = Result<Frame, E>>,
+    D: Timer + Unpin,
 {
-    type Error = Error<T::Error, E>;
+    fn poll_read_limited(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+        max_len: Option<u32>,
+    ) -> Poll<Result<usize, <Self as AsyncRead>::Error>> {
+        let poll = self.as_mut().poll_read_frame(cx, buf, max_len);
+        self.abort_on_error(poll)
+    }
 
-    fn poll_read(
-        self: Pin<&mut Self>,
+    /// Receive the first frame of the next message, returning its header.
+    ///
+    /// Any message that's already being read is discarded.
+    pub(crate) fn poll_message_header(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        max_len: Option<u32>,
+    ) -> Poll<Result<Header, <Self as AsyncRead>::Error>> {
+        if let State::Single { frame: None } | State::Consecutive { .. } = self.state {
+            let me = self.as_mut().project();
+            *me.state = State::Empty;
+            *me.unread = Unread::EMPTY;
+        }
+
+        let poll = self.as_mut().poll_header(cx, max_len);
+        ready!(self.as_mut().abort_on_error(poll))?;
+
+        let header = match &self.state {
+            State::Single { frame: Some(frame) } => Header {
+                len: frame.single_data().len() as u32,
+                kind: MessageKind::Single,
+                address: frame.address(),
+                id: frame.id(),
+            },
+            State::First { frame, .. } => Header {
+                len: frame.first_len(),
+                kind: MessageKind::Segmented,
+                address: frame.address(),
+                id: frame.id(),
+            },
+            _ => unreachable!("a new message was started"),
+        };
+        Poll::Ready(Ok(header))
+    }
+
+    /// Read the message started with [`Reader::poll_message_header`] into `buf`.
+    ///
+    /// This returns `true` once the whole message has been read.
+    pub(crate) fn poll_message_data(
+        mut self: Pin<&mut Self>,
         cx: &mut Context,
         buf: &mut [u8],
-    ) -> Poll<Result<usize, Self::Error>> {
+        max_len: Option<u32>,
+    ) -> Poll<Result<(usize, bool), <Self as AsyncRead>::Error>> {
+        let used = ready!(self.as_mut().poll_read_limited(cx, buf, max_len))?;
+
+        let is_finished = self.unread.is_empty() && self.state.is_finished();
+        if is_finished {
+            // Start the next message on the next read
+            *self.project().state = State::Empty;
+        }
+        Poll::Ready(Ok((used, is_finished)))
+    }
+
+    fn abort_on_error<U>(
+        self: Pin<&mut Self>,
+        poll: Poll<Result<U, <Self as AsyncRead>::Error>>,
+    ) -> Poll<Result<U, <Self as AsyncRead>::Error>> {
+        // Any error aborts the current message
+        if let Poll::Ready(Err(_)) = poll {
+            let me = self.project();
+            *me.state = State::Empty;
+            *me.unread = Unread::EMPTY;
+            me.deadline.cancel(me.timer).ok();
+        }
+
+        poll
+    }
+
+    /// Receive the first frame of the next message, if it hasn't been received yet.
+    fn poll_header(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        max_len: Option<u32>,
+    ) -> Poll<Result<(), <Self as AsyncRead>::Error>> {
         let mut me = self.project();
         loop {
             match me.state {
                 State::Empty => {
-                    let frame = ready!(me.transport.poll_next_unpin(cx))
-                        .ok_or(Error::UnexpectedEOF)?
-                        .map_err(Error::Receive)?;
-
-                    match frame.kind().ok_or(Error::UnknownFrameKind)? {
-                        Kind::Single => *me.state = State::Single { frame: Some(frame) },
-                        Kind::First => {
-                            let data = frame.first_data();
-                            *me.state = State::Consecutive {
-                                remaining_bytes: frame.first_len() - data.len() as u16,
-                                remaining_frames: 0,
-                                index: 0,
+                    let frame = if let Some(frame) = me.pending.take() {
+                        frame
+                    } else {
+                        ready!(me.transport.poll_next_unpin(cx))
+                            .ok_or(Error::UnexpectedEOF)?
+                            .map_err(Error::Receive)?
+                    };
+                    if me.config.strict_padding {
+                        frame
+                            .check_padding(me.config.format.padding())
+                            .map_err(Error::Parse)?;
+                    }
+
+                    let is_too_long = |len| max_len.is_some_and(|max_len| len > max_len);
+                    match frame.parse().map_err(Error::Parse)? {
+                        Pdu::Single { data } if is_too_long(data.len() as u32) => {
+                            break Poll::Ready(Err(Error::Overflow {
+                                len: data.len() as u32,
+                            }))
+                        }
+                        Pdu::Single { .. } => *me.state = State::Single { frame: Some(frame) },
+                        Pdu::First { len, .. } if is_too_long(len) => {
+                            *me.state = State::Overflow {
+                                len,
                                 is_flushing: false,
-                            };
-                            buf[..data.len()].copy_from_slice(data);
-                            break Poll::Ready(Ok(data.len()));
+                            }
                         }
-                        Kind::Consecutive | Kind::Flow => {
+                        Pdu::First { .. } => {
+                            *me.state = State::First {
+                                frame,
+                                waits: 0,
+                                is_waiting: false,
+                            }
+                        }
+                        Pdu::Consecutive { .. } | Pdu::Flow { .. } => {
                             break Poll::Ready(Err(Error::InvalidFrame))
                         }
                     }
                 }
-                State::Single { frame } => {
-                    let used = if let Some(frame) = frame.take() {
-                        let data = frame.single_data();
-                        buf[..data.len()].copy_from_slice(data);
+                State::Overflow { len, is_flushing } => {
+                    if !*is_flushing {
+                        let ready = me.transport.poll_ready_unpin(cx).map_err(Error::Transmit)?;
+                        ready!(me.deadline.poll_with(
+                            cx,
+                            me.timer.as_mut(),
+                            TimeoutKind::Ar,
+                            me.config.n_ar,
+                            ready
+                        ))?;
 
-                        data.len()
-                    } else {
-                        0
-                    };
-                    break Poll::Ready(Ok(used));
-                }
-                State::Consecutive {
-                    remaining_bytes,
-                    remaining_frames,
-                    index,
-                    is_flushing,
-                } => {
-                    if *remaining_bytes == 0 {
-                        break Poll::Ready(Ok(0));
+                        let frame = me.config.format.flow(FlowKind::Overflow, 0, 0);
+                        me.transport
+                            .start_send_unpin(frame)
+                            .map_err(Error::Transmit)?;
+                        *is_flushing = true;
                     }
 
-                    while *remaining_frames == 0 {
-                        if *is_flushing {
-                            ready!(me.transport.poll_flush_unpin(cx)).map_err(Error::Transmit)?;
-
-                            *remaining_frames = *me.block_len;
-                            *is_flushing = false;
-                        } else {
-                            ready!(me.transport.poll_ready_unpin(cx)).map_err(Error::Transmit)?;
-                            let frame = Frame::flow(FlowKind::Continue, *me.block_len, *me.st);
-                            me.transport
-                                .start_send_unpin(frame)
-                                .map_err(Error::Transmit)?;
-                            *is_flushing = true;
-                        }
+                    let flushed = me.transport.poll_flush_unpin(cx).map_err(Error::Transmit)?;
+                    ready!(me.deadline.poll_with(
+                        cx,
+                        me.timer.as_mut(),
+                        TimeoutKind::Ar,
+                        me.config.n_ar,
+                        flushed
+                    ))?;
+
+                    break Poll::Ready(Err(Error::Overflow { len: *len }));
+                }
+                _ => break Poll::Ready(Ok(())),
+            }
+        }
+    }
+
+    fn poll_read_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+        max_len: Option<u32>,
+    ) -> Poll<Result<usize, <Self as AsyncRead>::Error>> {
+        ready!(self.as_mut().poll_header(cx, max_len))?;
+
+        let mut me = self.project();
+
+        // Finish reading the last frame before receiving the next one
+        if !me.unread.is_empty() {
+            return Poll::Ready(Ok(me.unread.read(buf)));
+        }
+
+        match me.state {
+            State::Single { frame } => {
+                let used = if let Some(frame) = frame.take() {
+                    *me.unread = Unread::new(frame.single_data());
+                    me.unread.read(buf)
+                } else {
+                    // Signal the end of this message and start the next one on the next read
+                    *me.state = State::Empty;
+                    0
+                };
+                Poll::Ready(Ok(used))
+            }
+            State::First {
+                frame,
+                waits,
+                is_waiting,
+            } => {
+                let (waits, is_waiting) = (*waits, *is_waiting);
+                let Ok(Pdu::First { len, data }) = frame.parse() else {
+                    unreachable!("first frames are parsed with the header")
+                };
+                *me.unread = Unread::new(data);
+
+                *me.state = State::Consecutive {
+                    remaining_bytes: len - data.len() as u32,
+                    remaining_frames: Some(0),
+                    index: 0,
+                    waits,
+                    is_flushing: false,
+                    is_waiting,
+                };
+                Poll::Ready(Ok(me.unread.read(buf)))
+            }
+            State::Consecutive {
+                remaining_bytes,
+                remaining_frames,
+                index,
+                waits,
+                is_flushing,
+                is_waiting,
+            } => {
+                if *remaining_bytes == 0 {
+                    // Signal the end of this message and start the next one on the next read
+                    *me.state = State::Empty;
+                    return Poll::Ready(Ok(0));
+                }
+
+                while *remaining_frames == Some(0) {
+                    if *is_waiting {
+                        // Finish sending a wait frame from `poll_hold` first
+                        let flushed = me.transport.poll_flush_unpin(cx).map_err(Error::Transmit)?;
+                        ready!(me.deadline.poll_with(
+                            cx,
+                            me.timer.as_mut(),
+                            TimeoutKind::Ar,
+                            me.config.n_ar,
+                            flushed
+                        ))?;
+                        *is_waiting = false;
+                    } else if *is_flushing {
+                        let flushed = me.transport.poll_flush_unpin(cx).map_err(Error::Transmit)?;
+                        ready!(me.deadline.poll_with(
+                            cx,
+                            me.timer.as_mut(),
+                            TimeoutKind::Ar,
+                            me.config.n_ar,
+                            flushed
+                        ))?;
+
+                        // A block size of zero sends the rest of the message without flow control
+                        let block_len = me.config.block_len;
+                        *remaining_frames = (block_len != 0).then_some(block_len);
+                        *waits = 0;
+                        *is_flushing = false;
+                    } else {
+                        let ready = me.transport.poll_ready_unpin(cx).map_err(Error::Transmit)?;
+                        ready!(me.deadline.poll_with(
+                            cx,
+                            me.timer.as_mut(),
+                            TimeoutKind::Ar,
+                            me.config.n_ar,
+                            ready
+                        ))?;
+                        let frame = me.config.format.flow(
+                            FlowKind::Continue,
+                            me.config.block_len,
+                            me.config.st,
+                        );
+                        me.transport
+                            .start_send_unpin(frame)
+                            .map_err(Error::Transmit)?;
+                        *is_flushing = true;
                     }
+                }
 
-                    let frame = ready!(me.transport.poll_next_unpin(cx))
-                        .ok_or(Error::UnexpectedEOF)?
-                        .map_err(Error::Receive)?;
-                    if frame.kind().ok_or(Error::UnknownFrameKind)? != Kind::Consecutive {
-                        return Poll::Ready(Err(Error::InvalidFrame));
+                // Wait for the next consecutive frame
+                let next = me.transport.poll_next_unpin(cx);
+                let frame = ready!(me.deadline.poll_with(
+                    cx,
+                    me.timer.as_mut(),
+                    TimeoutKind::Cr,
+                    Some(me.config.n_cr),
+                    next
+                ))?
+                .ok_or(Error::UnexpectedEOF)?
+                .map_err(Error::Receive)?;
+                let (received, data) = match frame.parse().map_err(Error::Parse)? {
+                    Pdu::Consecutive { index, data } => (index, data),
+                    Pdu::Single { .. } | Pdu::First { .. } => {
+                        // A new message terminates the current one and is read next
+                        *me.pending = Some(frame);
+                        return Poll::Ready(Err(Error::Interrupted));
                     }
+                    Pdu::Flow { .. } => return Poll::Ready(Err(Error::InvalidFrame)),
+                };
 
-                    let data = frame.consecutive_data();
-                    let used = core::cmp::min(data.len(), *remaining_bytes as _);
-                    buf[..used].copy_from_slice(&data[..used]);
+                // Sequence numbers start at 1 after the first frame and wrap from 15 to 0
+                let expected = (*index + 1) & 0b1111;
+                if received != expected {
+                    return Poll::Ready(Err(Error::WrongSequenceNumber { expected, received }));
+                }
+
+                let len = core::cmp::min(data.len(), *remaining_bytes as _);
+                if me.config.strict_padding {
+                    // Only the last consecutive frame of a message can be padded
+                    let used = frame.len() - (data.len() - len);
+                    frame
+                        .check_padding_after(used, me.config.format.padding())
+                        .map_err(Error::Parse)?;
+                }
+                *me.unread = Unread::new(&data[..len]);
 
-                    *index += 1;
+                *remaining_bytes -= len as u32;
+                *index = expected;
+                if let Some(remaining_frames) = remaining_frames {
                     *remaining_frames -= 1;
+                }
+
+                Poll::Ready(Ok(me.unread.read(buf)))
+            }
+            State::Empty | State::Overflow { .. } => unreachable!("the header was received"),
+        }
+    }
+}
+
+impl<T, E, D> Reader<T, E, D>
+where
+    T: Sink<Frame> + Stream<Item = Result<Frame, E>>,
+    D: Timer + Unpin,
+{
+    /// Discard any message in progress, starting the next read from a new message.
+    ///
+    /// A message that interrupted the previous one is kept and read next.
+    pub fn reset(&mut self) {
+        self.state = State::Empty;
+        self.unread = Unread::EMPTY;
+        self.deadline.cancel(Pin::new(&mut self.timer)).ok();
+    }
+
+    /// Receive the header of the next message, before any flow control is sent.
+    ///
+    /// The message can then be read as usual, or rejected with [`Reader::reject_message`].
+    /// Calling this again before reading returns the same header,
+    /// and any message that's already being read is discarded.
+    pub async fn next_message_header(&mut self) -> Result<Header, <Self as AsyncRead>::Error>
+    where
+        Self: Unpin,
+    {
+        let max_len = self.config.max_message_len;
+        poll_fn(|cx| Pin::new(&mut *self).poll_message_header(cx, max_len)).await
+    }
+
+    /// Reject the message announced by [`Reader::next_message_header`].
+    ///
+    /// Segmented messages are rejected by sending an overflow flow control frame.
+    pub async fn reject_message(&mut self) -> Result<(), <Self as AsyncRead>::Error>
+    where
+        Self: Unpin,
+    {
+        if let State::First { frame, .. } = &self.state {
+            self.state = State::Overflow {
+                len: frame.first_len(),
+                is_flushing: false,
+            };
+
+            match poll_fn(|cx| Pin::new(&mut *self).poll_message_header(cx, None)).await {
+                Err(Error::Overflow { .. }) => {}
+                Err(error) => return Err(error),
+                Ok(_) => unreachable!("overflow is reported as an error"),
+            }
+        }
+
+        self.reset();
+        Ok(())
+    }
+
+    /// Read a whole message into `buf`, returning its length.
+    ///
+    /// Messages longer than `buf` are rejected with [`Error::Overflow`].
+    /// A message announced by [`Reader::next_message_header`] is read,
+    /// but any message partially read with [`AsyncRead`] is discarded.
+    pub async fn read_message(
+        &mut self,
+        buf: &mut [u8],
+    ) -> Result<usize, <Self as AsyncRead>::Error>
+    where
+        Self: Unpin,
+    {
+        let len = u32::try_from(buf.len()).unwrap_or(u32::MAX);
+        let max_len = Some(
+            self.config
+                .max_message_len
+                .map_or(len, |max_len| max_len.min(len)),
+        );
+
+        let is_too_long = |len| max_len.is_some_and(|max_len| len > max_len);
+        match &self.state {
+            State::Single { frame: Some(frame) }
+                if is_too_long(frame.single_data().len() as u32) =>
+            {
+                let len = frame.single_data().len() as u32;
+                self.reset();
+                return Err(Error::Overflow { len });
+            }
+            State::First { frame, .. } if is_too_long(frame.first_len()) => {
+                // Reject the announced message with an overflow flow control frame
+                self.state = State::Overflow {
+                    len: frame.first_len(),
+                    is_flushing: false,
+                };
+            }
+            State::Single { frame: Some(_) } | State::First { .. } => {}
+            _ => self.reset(),
+        }
+
+        let mut used = 0;
+        loop {
+            let (n, is_finished) =
+                poll_fn(|cx| Pin::new(&mut *self).poll_message_data(cx, &mut buf[used..], max_len))
+                    .await?;
+
+            used += n;
+            if is_finished {
+                break Ok(used);
+            }
+        }
+    }
+
+    /// Hold off the sender with wait flow control frames while `future` runs,
+    /// returning its output once it completes.
+    ///
+    /// See [`Reader::poll_hold`].
+    pub async fn hold<F>(&mut self, future: F) -> Result<F::Output, <Self as AsyncRead>::Error>
+    where
+        Self: Unpin,
+        F: Future,
+    {
+        let mut future = pin!(future);
+        let output = poll_fn(|cx| {
+            if let Poll::Ready(output) = future.as_mut().poll(cx) {
+                return Poll::Ready(Ok(output));
+            }
+
+            match Pin::new(&mut *self).poll_hold(cx) {
+                Poll::Ready(Err(error)) => Poll::Ready(Err(error)),
+                _ => Poll::Pending,
+            }
+        })
+        .await?;
+
+        let me = Pin::new(self).project();
+        me.deadline.cancel(me.timer).map_err(Error::Timer)?;
+
+        Ok(output)
+    }
+
+    /// Poll the reader while the consumer can't accept more data.
+    ///
+    /// Once the sender is waiting for flow control, such as after [`Reader::next_message_header`]
+    /// received a first frame, this sends a wait frame
+    /// and then another one every N_Br, up to N_WFTmax in a row.
+    /// The next read sends a continue frame instead.
+    ///
+    /// This only completes on error, such as exceeding N_WFTmax
+    /// or holding off a sender without a timer.
+    pub fn poll_hold(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<(), <Self as AsyncRead>::Error>> {
+        let poll = self.as_mut().poll_hold_frame(cx);
+        self.abort_on_error(poll)
+    }
+
+    fn poll_hold_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<(), <Self as AsyncRead>::Error>> {
+        let mut me = self.project();
+        let (waits, is_waiting) = match me.state {
+            // The sender waits for flow control after the first frame
+            State::First {
+                waits, is_waiting, ..
+            } => (waits, is_waiting),
+            State::Consecutive {
+                remaining_bytes,
+                remaining_frames: Some(0),
+                waits,
+                is_flushing: false,
+                is_waiting,
+                ..
+            } if *remaining_bytes > 0 => (waits, is_waiting),
+            // The sender isn't waiting for flow control
+            _ => return Poll::Pending,
+        };
+        if !D::IS_TIMED {
+            return Poll::Ready(Err(Error::MissingTimer));
+        }
+
+        loop {
+            if *is_waiting {
+                let flushed = me.transport.poll_flush_unpin(cx).map_err(Error::Transmit)?;
+                ready!(me.deadline.poll_with(
+                    cx,
+                    me.timer.as_mut(),
+                    TimeoutKind::Ar,
+                    me.config.n_ar,
+                    flushed
+                ))?;
+                *is_waiting = false;
+                *waits = waits.saturating_add(1);
+            }
 
-                    break Poll::Ready(Ok(used));
+            // Send the first wait frame right away and the rest every N_Br
+            if *waits > 0 {
+                match ready!(me.deadline.poll_with::<_, ()>(
+                    cx,
+                    me.timer.as_mut(),
+                    TimeoutKind::Br,
+                    Some(me.config.n_br),
+                    Poll::Pending
+                )) {
+                    Err(Expired::Timeout(TimeoutKind::Br)) => {}
+                    Err(expired) => return Poll::Ready(Err(expired.into())),
+                    Ok(()) => unreachable!(),
                 }
             }
+
+            if me.config.n_wft_max.is_some_and(|max| *waits >= max) {
+                return Poll::Ready(Err(Error::WaitLimitExceeded));
+            }
+
+            let ready = me.transport.poll_ready_unpin(cx).map_err(Error::Transmit)?;
+            ready!(me.deadline.poll_with(
+                cx,
+                me.timer.as_mut(),
+                TimeoutKind::Ar,
+                me.config.n_ar,
+                ready
+            ))?;
+            let frame = me.config.format.flow(FlowKind::Wait, 0, 0);
+            me.transport
+                .start_send_unpin(frame)
+                .map_err(Error::Transmit)?;
+            *is_waiting = true;
         }
     }
 }
+
+impl<T, E, D> AsyncRead for Reader<T, E, D>
+where
+    T: Sink<Frame> + Stream<Item = Result<Frame, E>>,
+    D: Timer + Unpin,
+{
+    type Error = Error<T::Error, E, D::Error>;
+
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Self::Error>> {
+        let max_len = self.config.max_message_len;
+        self.poll_read_limited(cx, buf, max_len)
+    }
+}
@@ -0,0 +1,165 @@
+use async_hal::delay::DelayMs;
+use core::{
+    convert::Infallible,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use futures::ready;
+
+/// Kind of protocol timeout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeoutKind {
+    /// N_As: transmission of a frame by the sender.
+    As,
+    /// N_Ar: transmission of a flow control frame by the receiver.
+    Ar,
+    /// N_Br: interval between wait flow control frames sent by the receiver.
+    Br,
+    /// N_Bs: reception of the next flow control frame by the sender.
+    Bs,
+    /// N_Cr: reception of the next consecutive frame by the receiver.
+    Cr,
+}
+
+/// Timer for delays and protocol timeouts.
+///
+/// This is implemented for any [`DelayMs`] timer,
+/// with durations rounded up to the next millisecond.
+/// Durations longer than the delay type can hold, such as 255ms for a `u8` delay, are saturated.
+/// Timers implementing this directly can honour sub-millisecond separation times.
+pub trait Timer {
+    /// The error returned on failure.
+    type Error;
+
+    /// Whether this timer measures time.
+    ///
+    /// Timeouts are disabled for timers that don't, and their delays complete right away.
+    const IS_TIMED: bool = true;
+
+    /// Start a new delay of `duration`.
+    fn start(&mut self, duration: Duration) -> Result<(), Self::Error>;
+
+    /// Poll the current delay, completing once it expires.
+    fn poll_delay(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>>;
+
+    /// Attempt to cancel a delay in progress.
+    fn cancel(&mut self) -> Result<(), Self::Error>;
+}
+
+impl<D> Timer for D
+where
+    D: DelayMs,
+    D::Delay: TryFrom<u32>,
+{
+    type Error = D::Error;
+
+    /// # Panics
+    /// Panics if the delay type can't hold 255ms.
+    fn start(&mut self, duration: Duration) -> Result<(), Self::Error> {
+        let ms = duration.as_micros().div_ceil(1000).min(u32::MAX as _) as u32;
+
+        // Saturate to the largest delay of common integer types
+        let delay = [ms, ms.min(u16::MAX.into()), ms.min(u8::MAX.into())]
+            .into_iter()
+            .find_map(|ms| D::Delay::try_from(ms).ok())
+            .expect("delay type can't hold 255ms");
+        DelayMs::start(self, delay)
+    }
+
+    fn poll_delay(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.poll_delay_ms(cx)
+    }
+
+    fn cancel(&mut self) -> Result<(), Self::Error> {
+        DelayMs::cancel(self)
+    }
+}
+
+/// Timer that doesn't measure time, disabling timeouts.
+///
+/// Delays such as separation times complete right away.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoTimer;
+
+impl Timer for NoTimer {
+    type Error = Infallible;
+
+    const IS_TIMED: bool = false;
+
+    fn start(&mut self, _duration: Duration) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn poll_delay(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn cancel(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Error from polling with a [`Deadline`].
+pub(crate) enum Expired<E> {
+    /// The timeout expired.
+    Timeout(TimeoutKind),
+    /// The timer failed.
+    Timer(E),
+}
+
+/// Timeout currently running on a [`Timer`].
+#[derive(Debug, Default)]
+pub(crate) struct Deadline {
+    kind: Option<TimeoutKind>,
+}
+
+impl Deadline {
+    /// Wait for `poll` to complete within a timeout of `kind`.
+    ///
+    /// The timer is started with `duration` the first time `poll` is pending,
+    /// and cancelled once it completes.
+    /// A `duration` of `None` or a timer that doesn't measure time disables the timeout.
+    pub(crate) fn poll_with<D, T>(
+        &mut self,
+        cx: &mut Context,
+        mut timer: Pin<&mut D>,
+        kind: TimeoutKind,
+        duration: Option<Duration>,
+        poll: Poll<T>,
+    ) -> Poll<Result<T, Expired<D::Error>>>
+    where
+        D: Timer + Unpin,
+    {
+        if let Poll::Ready(output) = poll {
+            self.cancel(timer).map_err(Expired::Timer)?;
+            return Poll::Ready(Ok(output));
+        }
+
+        let Some(duration) = duration.filter(|_| D::IS_TIMED) else {
+            return Poll::Pending;
+        };
+
+        if self.kind != Some(kind) {
+            timer.start(duration).map_err(Expired::Timer)?;
+            self.kind = Some(kind);
+        }
+
+        ready!(timer.as_mut().poll_delay(cx)).map_err(Expired::Timer)?;
+        self.kind = None;
+
+        Poll::Ready(Err(Expired::Timeout(kind)))
+    }
+
+    /// Cancel the running timeout, if any.
+    pub(crate) fn cancel<D>(&mut self, mut timer: Pin<&mut D>) -> Result<(), D::Error>
+    where
+        D: Timer + Unpin,
+    {
+        if self.kind.take().is_some() {
+            timer.cancel()?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,14 +1,17 @@
+use super::timer::{Deadline, Expired, TimeoutKind, Timer};
 use crate::{
-    frame::{FlowKind, Kind},
+    frame::{FlowKind, Format, ParseError, Pdu, SeparationTime},
     Frame,
 };
-use async_hal::{delay::DelayMs, io::AsyncWrite};
+use async_hal::io::AsyncWrite;
 use core::{
+    future::poll_fn,
     marker::PhantomData,
     pin::Pin,
     task::{Context, Poll},
+    time::Duration,
 };
-use futures::{ready, Sink, SinkExt, Stream};
+use futures::{ready, Sink, Stream};
 use pin_project_lite::pin_project;
 
 enum State {
@@ -18,9 +21,11 @@ enum State {
     },
     Consecutive {
         pos: Option<u8>,
-        remaining: u8,
+        remaining_bytes: u32,
+        remaining: Option<u8>,
+        waits: u8,
         is_delaying: bool,
-        st: u8,
+        st: SeparationTime,
     },
 }
 
@@ -29,9 +34,124 @@ pub enum Error<T, R, D> {
     Transmit(T),
     Receive(R),
     Delay(D),
+    Parse(ParseError),
     InvalidFrame,
-    Aborted,
+    /// The receiver can't accept a message of this size.
+    Overflow,
+    /// The receiver sent more wait frames in a row than the configured N_WFTmax.
+    WaitLimitExceeded,
     UnexpectedEOF,
+    /// The receiver didn't respond in time and the message was aborted.
+    Timeout(TimeoutKind),
+}
+
+impl<T, R, D> From<Expired<D>> for Error<T, R, D> {
+    fn from(expired: Expired<D>) -> Self {
+        match expired {
+            Expired::Timeout(kind) => Self::Timeout(kind),
+            Expired::Timer(error) => Self::Delay(error),
+        }
+    }
+}
+
+/// Configuration of a [`Writer`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    format: Format,
+    n_as: Option<Duration>,
+    n_bs: Duration,
+    n_wft_max: Option<u8>,
+    strict_padding: bool,
+}
+
+impl Config {
+    /// Create a builder for a new configuration.
+    pub fn builder() -> Builder {
+        Builder {
+            config: Self::default(),
+        }
+    }
+
+    /// Returns the format of sent frames.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Returns the timeout for the transport to accept a frame (N_As), if any.
+    pub fn n_as(&self) -> Option<Duration> {
+        self.n_as
+    }
+
+    /// Returns the timeout for receiving the next flow control frame (N_Bs).
+    pub fn n_bs(&self) -> Duration {
+        self.n_bs
+    }
+
+    /// Returns the maximum number of wait frames accepted in a row (N_WFTmax), if any.
+    pub fn n_wft_max(&self) -> Option<u8> {
+        self.n_wft_max
+    }
+
+    /// Returns `true` if received frames must be padded like the configured format.
+    pub fn strict_padding(&self) -> bool {
+        self.strict_padding
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            format: Format::default(),
+            n_as: None,
+            n_bs: Duration::from_millis(1000),
+            n_wft_max: None,
+            strict_padding: false,
+        }
+    }
+}
+
+/// Builder for a writer [`Config`].
+#[derive(Clone, Copy, Debug)]
+pub struct Builder {
+    config: Config,
+}
+
+impl Builder {
+    /// Segment messages into frames of `format`.
+    pub fn format(mut self, format: Format) -> Self {
+        self.config.format = format;
+        self
+    }
+
+    /// Set the timeout for the transport to accept a frame (N_As).
+    pub fn n_as(mut self, n_as: Duration) -> Self {
+        self.config.n_as = Some(n_as);
+        self
+    }
+
+    /// Set the timeout for receiving the next flow control frame (N_Bs).
+    pub fn n_bs(mut self, n_bs: Duration) -> Self {
+        self.config.n_bs = n_bs;
+        self
+    }
+
+    /// Set the maximum number of wait frames accepted in a row (N_WFTmax).
+    pub fn n_wft_max(mut self, n_wft_max: u8) -> Self {
+        self.config.n_wft_max = Some(n_wft_max);
+        self
+    }
+
+    /// Reject received frames that aren't padded like the configured format
+    /// with [`ParseError::InvalidPadding`].
+    pub fn strict_padding(mut self, strict_padding: bool) -> Self {
+        self.config.strict_padding = strict_padding;
+        self
+    }
+
+    /// Build the writer configuration.
+    pub fn build(self) -> Config {
+        self.config
+    }
 }
 
 pin_project! {
@@ -42,47 +162,75 @@ pin_project! {
         #[pin]
         delay: D,
         state: State,
+        config: Config,
+        deadline: Deadline,
         _marker: PhantomData<E>
     }
 }
 
 impl<T, E, D> Writer<T, E, D> {
     pub fn new(transport: T, delay: D) -> Self {
+        Self::with_config(transport, delay, Config::default())
+    }
+
+    /// Create a new writer that segments messages into frames of `format`.
+    pub fn with_format(transport: T, delay: D, format: Format) -> Self {
+        Self::with_config(transport, delay, Config::builder().format(format).build())
+    }
+
+    /// Create a new writer with the given configuration.
+    pub fn with_config(transport: T, delay: D, config: Config) -> Self {
         Self {
             transport,
             delay,
             state: State::Empty,
+            config,
+            deadline: Deadline::default(),
             _marker: PhantomData,
         }
     }
+
+    /// Returns a reference to the underlying transport.
+    pub fn get_ref(&self) -> &T {
+        &self.transport
+    }
+
+    /// Returns a mutable reference to the underlying transport.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
 }
 
-impl<T, E, D> AsyncWrite for Writer<T, E, D>
+impl<T, E, D> Writer<T, E, D>
 where
     T: Sink<Frame> + Stream<Item = Result<Frame, E>>,
-    D: DelayMs + Unpin,
-    D::Delay: From<u8>,
+    D: Timer + Unpin,
 {
-    type Error = Error<T::Error, E, D::Error>;
-
-    fn poll_write(
+    fn poll_write_frame(
         self: Pin<&mut Self>,
         cx: &mut Context,
         buf: &[u8],
-    ) -> Poll<Result<usize, Self::Error>> {
+    ) -> Poll<Result<usize, <Self as AsyncWrite>::Error>> {
         let mut me = self.project();
         loop {
             match me.state {
                 State::Empty => {
+                    if buf.is_empty() {
+                        // Empty messages can't be sent
+                        break Poll::Ready(Ok(0));
+                    }
+
                     // Start a new transfer
-                    *me.state = if let Some(frame) = Frame::single(buf) {
+                    *me.state = if let Some(frame) = me.config.format.single(buf) {
                         State::Single { frame: Some(frame) }
                     } else {
                         State::Consecutive {
                             pos: None,
-                            remaining: 0,
+                            remaining_bytes: 0,
+                            remaining: Some(0),
+                            waits: 0,
                             is_delaying: false,
-                            st: 0,
+                            st: SeparationTime::ZERO,
                         }
                     };
                 }
@@ -92,8 +240,19 @@ where
                         break Poll::Ready(Ok(0));
                     }
 
-                    // Send a single frame
-                    ready!(me.transport.as_mut().poll_ready(cx)).map_err(Error::Transmit)?;
+                    // Send a single frame once the transport is ready
+                    let ready = me
+                        .transport
+                        .as_mut()
+                        .poll_ready(cx)
+                        .map_err(Error::Transmit)?;
+                    ready!(me.deadline.poll_with(
+                        cx,
+                        me.delay.as_mut(),
+                        TimeoutKind::As,
+                        me.config.n_as,
+                        ready
+                    ))?;
 
                     // Take the current frame so it can only be written once
                     let frame = frame.take().unwrap();
@@ -107,100 +266,216 @@ where
                 }
                 State::Consecutive {
                     pos,
+                    remaining_bytes,
                     remaining,
+                    waits,
                     is_delaying,
                     st,
                 } => {
                     // Poll the current delay if it's in progress
                     if *is_delaying {
-                        ready!(me.delay.as_mut().poll_delay_ms(cx)).map_err(Error::Delay)?;
+                        ready!(me.delay.as_mut().poll_delay(cx)).map_err(Error::Delay)?;
                         *is_delaying = false;
                     }
 
                     if let Some(pos) = pos {
                         // Check if we have any remaining frames left
-                        if *remaining == 0 {
+                        if *remaining == Some(0) {
                             // Wait for the next frame from `rx`
-                            let frame = ready!(me.transport.as_mut().poll_next(cx))
-                                .ok_or(Error::UnexpectedEOF)?
-                                .map_err(Error::Receive)?;
+                            let next = me.transport.as_mut().poll_next(cx);
+                            let frame = ready!(me.deadline.poll_with(
+                                cx,
+                                me.delay.as_mut(),
+                                TimeoutKind::Bs,
+                                Some(me.config.n_bs),
+                                next
+                            ))?
+                            .ok_or(Error::UnexpectedEOF)?
+                            .map_err(Error::Receive)?;
+                            if me.config.strict_padding {
+                                frame
+                                    .check_padding(me.config.format.padding())
+                                    .map_err(Error::Parse)?;
+                            }
 
                             // Make sure the frame is control flow
-                            if frame.kind() != Some(Kind::Flow) {
+                            let Pdu::Flow {
+                                kind,
+                                block_len,
+                                st: flow_st,
+                            } = frame.parse().map_err(Error::Parse)?
+                            else {
                                 return Poll::Ready(Err(Error::InvalidFrame));
-                            }
+                            };
 
                             // Handle control flow kinds
-                            match frame.flow_kind() {
-                                FlowKind::Continue => {}
+                            match kind {
+                                FlowKind::Continue => *waits = 0,
                                 FlowKind::Wait => {
-                                    // Delay for the received wait time
-                                    me.delay
-                                        .as_mut()
-                                        .start(frame.flow_st().into())
-                                        .map_err(Error::Delay)?;
-                                    *is_delaying = true;
+                                    // Keep waiting for the next flow control frame,
+                                    // restarting the N_Bs timeout
+                                    *waits = waits.saturating_add(1);
+                                    if me.config.n_wft_max.is_some_and(|max| *waits > max) {
+                                        return Poll::Ready(Err(Error::WaitLimitExceeded));
+                                    }
 
                                     continue;
                                 }
-                                FlowKind::Abort => {
+                                FlowKind::Overflow => {
                                     // Abort this transfer
-                                    *me.state = State::Empty;
-                                    return Poll::Ready(Err(Error::Aborted));
+                                    return Poll::Ready(Err(Error::Overflow));
                                 }
                             }
 
-                            *remaining = frame.flow_len();
-                            *st = frame.flow_st();
+                            // A block size of zero sends the rest of the message without flow control
+                            *remaining = (block_len != 0).then_some(block_len);
+                            *st = flow_st;
                         }
 
                         // Send a consecutive frame for the current transfer in progress
-                        let (frame, used) = Frame::consecutive(*pos, buf);
-                        ready!(poll_send(cx, me.transport.as_mut(), frame))?;
+                        let data = &buf[..buf.len().min(*remaining_bytes as usize)];
+                        let (frame, used) = me
+                            .config
+                            .format
+                            .consecutive(*pos, data)
+                            .expect("sequence numbers wrap at 4 bits");
+                        let ready = me
+                            .transport
+                            .as_mut()
+                            .poll_ready(cx)
+                            .map_err(Error::Transmit)?;
+                        ready!(me.deadline.poll_with(
+                            cx,
+                            me.delay.as_mut(),
+                            TimeoutKind::As,
+                            me.config.n_as,
+                            ready
+                        ))?;
+                        me.transport.start_send(frame).map_err(Error::Transmit)?;
 
                         // Prepare the state for the next frame
-                        *pos += 1;
-                        *remaining -= 1;
+                        *remaining_bytes -= used as u32;
+                        if *remaining_bytes == 0 {
+                            // This transfer is finished
+                            *me.state = State::Empty;
+                            break Poll::Ready(Ok(used));
+                        }
+
+                        // Sequence numbers wrap from 15 to 0
+                        *pos = (*pos + 1) & 0b1111;
+                        if let Some(remaining) = remaining {
+                            *remaining -= 1;
+                        }
 
                         // Delay for the received seperation time
-                        me.delay
-                            .as_mut()
-                            .start(st.clone().into())
-                            .map_err(Error::Delay)?;
-                        *is_delaying = true;
+                        let st = Duration::from(*st);
+                        if !st.is_zero() {
+                            me.delay.as_mut().start(st).map_err(Error::Delay)?;
+                            *is_delaying = true;
+                        }
 
                         break Poll::Ready(Ok(used));
                     } else {
                         // Send the first frame of this sequence
-                        let (frame, used) = Frame::first(buf);
-                        ready!(poll_send(cx, me.transport.as_mut(), frame))?;
+                        let (frame, used) = me.config.format.first(buf);
+                        let ready = me
+                            .transport
+                            .as_mut()
+                            .poll_ready(cx)
+                            .map_err(Error::Transmit)?;
+                        ready!(me.deadline.poll_with(
+                            cx,
+                            me.delay.as_mut(),
+                            TimeoutKind::As,
+                            me.config.n_as,
+                            ready
+                        ))?;
+                        me.transport.start_send(frame).map_err(Error::Transmit)?;
 
-                        *pos = Some(0);
+                        // The first consecutive frame has a sequence number of 1
+                        *pos = Some(1);
+                        *remaining_bytes = (buf.len() - used) as u32;
                         break Poll::Ready(Ok(used));
                     }
                 }
             }
         }
     }
+}
 
-    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+impl<T, E, D> Writer<T, E, D>
+where
+    T: Sink<Frame> + Stream<Item = Result<Frame, E>>,
+    D: Timer + Unpin,
+{
+    /// Write a whole message, returning once its last frame has been sent.
+    ///
+    /// Empty messages aren't sent.
+    pub async fn write_message(&mut self, message: &[u8]) -> Result<(), <Self as AsyncWrite>::Error>
+    where
+        Self: Unpin,
+    {
+        let mut pos = 0;
+        poll_fn(|cx| Pin::new(&mut *self).poll_write_message(cx, message, &mut pos)).await?;
+
+        poll_fn(|cx| Pin::new(&mut *self).poll_flush(cx)).await
+    }
+
+    /// Write the rest of `message` from `pos`, completing once its last frame has been sent.
+    pub(crate) fn poll_write_message(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        message: &[u8],
+        pos: &mut usize,
+    ) -> Poll<Result<(), <Self as AsyncWrite>::Error>> {
+        loop {
+            *pos += ready!(self.as_mut().poll_write(cx, &message[*pos..]))?;
+
+            if let State::Empty = self.state {
+                break Poll::Ready(Ok(()));
+            }
+        }
+    }
+
+    pub(crate) fn poll_close(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<(), <Self as AsyncWrite>::Error>> {
         self.project()
             .transport
-            .poll_flush(cx)
+            .poll_close(cx)
             .map_err(Error::Transmit)
     }
 }
 
-fn poll_send<S, R, D>(
-    cx: &mut Context,
-    mut tx: S,
-    frame: Frame,
-) -> Poll<Result<(), Error<S::Error, R, D>>>
+impl<T, E, D> AsyncWrite for Writer<T, E, D>
 where
-    S: Sink<Frame> + Unpin,
+    T: Sink<Frame> + Stream<Item = Result<Frame, E>>,
+    D: Timer + Unpin,
 {
-    ready!(tx.poll_ready_unpin(cx)).map_err(Error::Transmit)?;
-    tx.start_send_unpin(frame).map_err(Error::Transmit)?;
+    type Error = Error<T::Error, E, D::Error>;
 
-    Poll::Ready(Ok(()))
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Self::Error>> {
+        let poll = self.as_mut().poll_write_frame(cx, buf);
+
+        // Any error aborts the current transfer
+        if let Poll::Ready(Err(_)) = poll {
+            let me = self.project();
+            *me.state = State::Empty;
+            me.deadline.cancel(me.delay).ok();
+        }
+
+        poll
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.project()
+            .transport
+            .poll_flush(cx)
+            .map_err(Error::Transmit)
+    }
 }
@@ -0,0 +1,112 @@
+#![allow(dead_code)]
+
+use embedded_hal::can::{ExtendedId, Frame, Id, StandardId};
+use futures::{stream, Sink, Stream, StreamExt};
+use std::{
+    convert::Infallible,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+/// Classic CAN frame with up to 8 bytes of data.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CanFrame {
+    pub id: Id,
+    pub data: Vec<u8>,
+}
+
+impl Frame for CanFrame {
+    fn new(id: impl Into<Id>, data: &[u8]) -> Option<Self> {
+        (data.len() <= 8).then(|| Self {
+            id: id.into(),
+            data: data.to_vec(),
+        })
+    }
+
+    fn new_remote(_id: impl Into<Id>, _dlc: usize) -> Option<Self> {
+        None
+    }
+
+    fn is_extended(&self) -> bool {
+        matches!(self.id, Id::Extended(_))
+    }
+
+    fn is_remote_frame(&self) -> bool {
+        false
+    }
+
+    fn id(&self) -> Id {
+        self.id
+    }
+
+    fn dlc(&self) -> usize {
+        self.data.len()
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// CAN bus that receives `rx` and records transmitted frames.
+pub struct Bus {
+    pub tx: Arc<Mutex<Vec<CanFrame>>>,
+    rx: stream::BoxStream<'static, Result<CanFrame, ()>>,
+}
+
+impl Bus {
+    pub fn new(rx: Vec<CanFrame>) -> Self {
+        Self {
+            tx: Arc::default(),
+            rx: stream::iter(rx.into_iter().map(Ok)).boxed(),
+        }
+    }
+
+    /// Create a bus that stops receiving after `rx`.
+    pub fn stalled(rx: Vec<CanFrame>) -> Self {
+        Self {
+            tx: Arc::default(),
+            rx: stream::iter(rx.into_iter().map(Ok))
+                .chain(stream::pending())
+                .boxed(),
+        }
+    }
+}
+
+impl Stream for Bus {
+    type Item = Result<CanFrame, ()>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_next_unpin(cx)
+    }
+}
+
+impl Sink<CanFrame> for Bus {
+    type Error = Infallible;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: CanFrame) -> Result<(), Self::Error> {
+        self.tx.lock().unwrap().push(item);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+pub fn id(raw: u16) -> StandardId {
+    StandardId::new(raw).unwrap()
+}
+
+pub fn extended_id(raw: u32) -> ExtendedId {
+    ExtendedId::new(raw).unwrap()
+}
@@ -0,0 +1,139 @@
+#![allow(dead_code)]
+
+use async_hal::delay::DelayMs;
+use std::{
+    convert::Infallible,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{stream, Sink, Stream, StreamExt};
+use iso_tp::{transport::Timer, Frame};
+
+pub struct Mock {
+    tx: Arc<Mutex<Vec<Frame>>>,
+    rx: stream::BoxStream<'static, Result<Frame, ()>>,
+}
+
+impl Mock {
+    pub fn new(tx: Vec<Frame>, rx: Vec<Result<Frame, ()>>) -> Self {
+        Self {
+            tx: Arc::new(Mutex::new(tx)),
+            rx: stream::iter(rx).boxed(),
+        }
+    }
+
+    /// Create a mock that stops responding after receiving `rx`.
+    pub fn stalled(rx: Vec<Result<Frame, ()>>) -> Self {
+        Self {
+            tx: Arc::default(),
+            rx: stream::iter(rx).chain(stream::pending()).boxed(),
+        }
+    }
+
+    /// Returns a handle to the frames sent to this mock.
+    pub fn sent(&self) -> Arc<Mutex<Vec<Frame>>> {
+        self.tx.clone()
+    }
+}
+
+impl Stream for Mock {
+    type Item = Result<Frame, ()>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_next_unpin(cx)
+    }
+}
+
+impl Sink<Frame> for Mock {
+    type Error = Infallible;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Frame) -> Result<(), Self::Error> {
+        self.tx.lock().unwrap().push(item);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Delay that expires immediately.
+pub struct MockDelay;
+
+impl DelayMs for MockDelay {
+    type Delay = u8;
+
+    type Error = ();
+
+    fn start(&mut self, _ms: Self::Delay) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn poll_delay_ms(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn cancel(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Timer that expires immediately, recording each started duration.
+#[derive(Default)]
+pub struct MockTimer {
+    started: Arc<Mutex<Vec<Duration>>>,
+}
+
+impl MockTimer {
+    /// Returns a handle to the durations started on this timer.
+    pub fn started(&self) -> Arc<Mutex<Vec<Duration>>> {
+        self.started.clone()
+    }
+}
+
+impl Timer for MockTimer {
+    type Error = ();
+
+    fn start(&mut self, duration: Duration) -> Result<(), Self::Error> {
+        self.started.lock().unwrap().push(duration);
+        Ok(())
+    }
+
+    fn poll_delay(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn cancel(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Timer that never expires.
+pub struct PendingTimer;
+
+impl Timer for PendingTimer {
+    type Error = ();
+
+    fn start(&mut self, _duration: Duration) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn poll_delay(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Poll::Pending
+    }
+
+    fn cancel(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+use core::time::Duration;
+use iso_tp::{
+    frame::{Addressing, FlowKind, Format, Kind, Padding, ParseError, Pdu, SeparationTime},
+    Frame,
+};
+
+#[test]
+fn it_pads_classic_frames() {
+    let frame = Frame::single(b"abc").unwrap();
+    assert_eq!(frame.as_ref(), &[0x03, b'a', b'b', b'c', 0, 0, 0, 0]);
+}
+
+#[test]
+fn it_rejects_empty_single_frames() {
+    assert!(Frame::single(&[]).is_none());
+    assert!(Format::CAN_FD.single(&[]).is_none());
+}
+
+#[test]
+fn it_pads_with_a_configured_byte() {
+    let format = Format::CAN.with_padding(Padding::Byte(0xCC));
+    let frame = format.flow(FlowKind::Continue, 8, 0);
+    assert_eq!(frame.as_ref(), &[0x30, 8, 0, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC]);
+}
+
+#[test]
+fn it_sends_unpadded_frames() {
+    let format = Format::CAN.with_padding(Padding::Disabled);
+    assert_eq!(format.single(b"abc").unwrap().as_ref(), b"\x03abc");
+    assert_eq!(
+        format.flow(FlowKind::Continue, 8, 0).as_ref(),
+        &[0x30, 8, 0]
+    );
+
+    // CAN FD frames over 8 bytes still use a valid data length
+    let frame = Format::CAN_FD
+        .with_padding(Padding::Disabled)
+        .single(&[0xAB; 9])
+        .unwrap();
+    assert_eq!(frame.len(), 12);
+    assert_eq!(&frame.as_ref()[11..], &[0xCC]);
+}
+
+#[test]
+fn it_checks_padding() {
+    let frame = Frame::single(b"abc").unwrap();
+    assert_eq!(frame.check_padding(Padding::Byte(0)), Ok(()));
+    assert_eq!(
+        frame.check_padding(Padding::Byte(0xAA)),
+        Err(ParseError::InvalidPadding)
+    );
+    assert_eq!(
+        frame.check_padding(Padding::Disabled),
+        Err(ParseError::InvalidPadding)
+    );
+
+    let frame = Frame::from_bytes(b"\x03abc");
+    assert_eq!(frame.check_padding(Padding::Disabled), Ok(()));
+}
+
+#[test]
+fn it_escapes_fd_single_frames() {
+    let data = [0xAB; 20];
+    assert!(Format::CAN.single(&data).is_none());
+
+    let frame = Format::CAN_FD.single(&data).unwrap();
+    assert_eq!(frame.len(), 24);
+    assert_eq!(&frame.as_ref()[..2], &[0x00, 20]);
+
+    let frame = Frame::from_bytes(frame.as_ref());
+    assert_eq!(frame.kind(), Some(Kind::Single));
+    assert_eq!(frame.single_data(), &data);
+}
+
+#[test]
+fn it_fills_fd_first_frames() {
+    let data = [0xCD; 100];
+    let (frame, used) = Format::CAN_FD.first(&data);
+    assert_eq!(used, 62);
+    assert_eq!(frame.len(), 64);
+    assert_eq!(frame.first_len(), 100);
+
+    let (frame, used) = Format::CAN_FD.consecutive(1, &data[used..]).unwrap();
+    assert_eq!(used, 38);
+    assert_eq!(frame.len(), 48);
+    assert_eq!(&frame.consecutive_data()[..used], &data[62..]);
+}
+
+#[test]
+fn it_rejects_invalid_data_lengths() {
+    assert!(Format::new(10).is_none());
+    assert_eq!(Format::new(32).unwrap().tx_dl(), 32);
+}
+
+#[test]
+fn it_escapes_long_first_frames() {
+    let data = [0xEF; 5000];
+    let (frame, used) = Frame::first(&data);
+    assert_eq!(used, 2);
+    assert_eq!(&frame.as_ref()[..6], &[0x10, 0x00, 0x00, 0x00, 0x13, 0x88]);
+
+    let frame = Frame::from_bytes(frame.as_ref());
+    assert_eq!(frame.first_len(), 5000);
+    assert_eq!(frame.first_data(), &data[..2]);
+}
+
+#[test]
+fn it_encodes_first_frame_lengths() {
+    let data = [0; 0x123];
+    let (frame, _) = Frame::first(&data);
+    assert_eq!(&frame.as_ref()[..2], &[0x11, 0x23]);
+    assert_eq!(frame.first_len(), 0x123);
+}
+
+#[test]
+fn it_parses_frames() {
+    let frame = Frame::single(b"abc").unwrap();
+    assert_eq!(frame.parse(), Ok(Pdu::Single { data: b"abc" }));
+
+    let frame = Frame::flow(FlowKind::Wait, 8, 20);
+    assert_eq!(
+        frame.parse(),
+        Ok(Pdu::Flow {
+            kind: FlowKind::Wait,
+            block_len: 8,
+            st: SeparationTime::from_bits(20)
+        })
+    );
+
+    let (frame, _) = Frame::first(b"Hello World!");
+    assert_eq!(
+        frame.parse(),
+        Ok(Pdu::First {
+            len: 12,
+            data: b"Hello "
+        })
+    );
+}
+
+#[test]
+fn it_rejects_malformed_frames() {
+    assert_eq!(Pdu::try_from(&[][..]), Err(ParseError::Empty));
+    assert_eq!(
+        Pdu::try_from(&[0x0F, 0, 0, 0, 0, 0, 0, 0][..]),
+        Err(ParseError::InvalidSingleLength(15))
+    );
+    assert_eq!(
+        Pdu::try_from(&[0x05, 1, 2][..]),
+        Err(ParseError::InvalidSingleLength(5))
+    );
+    assert_eq!(
+        Pdu::try_from(&[0x3F, 0, 0][..]),
+        Err(ParseError::UnknownFlowKind(0xF))
+    );
+    assert_eq!(Pdu::try_from(&[0x30][..]), Err(ParseError::Truncated));
+    assert_eq!(
+        Pdu::try_from(&[0x10, 0x05, 1, 2, 3, 4, 5, 6][..]),
+        Err(ParseError::InvalidFirstLength(5))
+    );
+    assert_eq!(Pdu::try_from(&[0x70][..]), Err(ParseError::UnknownKind(7)));
+}
+
+#[test]
+fn it_shifts_pci_for_extended_addressing() {
+    let format = Format::CAN.with_addressing(Addressing::Extended {
+        target: 0xF1,
+        source: 0x10,
+    });
+
+    let frame = format.single(b"abcdef").unwrap();
+    assert_eq!(
+        frame.as_ref(),
+        &[0xF1, 0x06, b'a', b'b', b'c', b'd', b'e', b'f']
+    );
+    assert!(format.single(b"abcdefg").is_none());
+
+    let (frame, used) = format.first(b"Hello World!");
+    assert_eq!(used, 5);
+    assert_eq!(frame.address(), Some(0xF1));
+
+    let frame = Frame::from_addressed_bytes(frame.as_ref());
+    assert_eq!(
+        frame.parse(),
+        Ok(Pdu::First {
+            len: 12,
+            data: b"Hello"
+        })
+    );
+}
+
+#[test]
+fn it_encodes_separation_times() {
+    for (duration, bits) in [
+        (Duration::ZERO, 0x00),
+        (Duration::from_micros(100), 0xF1),
+        (Duration::from_micros(250), 0xF3),
+        (Duration::from_micros(900), 0xF9),
+        (Duration::from_micros(1500), 0x02),
+        (Duration::from_millis(127), 0x7F),
+        (Duration::from_secs(1), 0x7F),
+    ] {
+        assert_eq!(SeparationTime::from(duration).to_bits(), bits);
+    }
+}
+
+#[test]
+fn it_decodes_separation_times() {
+    for (bits, duration) in [
+        (0x00, Duration::ZERO),
+        (0x0A, Duration::from_millis(10)),
+        (0xF5, Duration::from_micros(500)),
+        (0x80, Duration::from_millis(127)),
+        (0xF0, Duration::from_millis(127)),
+        (0xFA, Duration::from_millis(127)),
+    ] {
+        let st = SeparationTime::from_bits(bits);
+        assert_eq!(Duration::from(st), duration);
+        assert_eq!(st.is_reserved(), duration == Duration::from_millis(127));
+    }
+}
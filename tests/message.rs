@@ -0,0 +1,88 @@
+#![cfg(all(feature = "transport", feature = "alloc", feature = "heapless"))]
+
+mod common;
+
+mod tests {
+    use crate::common::{Mock, MockDelay};
+    use futures::{SinkExt, StreamExt};
+    use iso_tp::{
+        frame::FlowKind,
+        transport::{reader::Error, MessageSink, MessageStream},
+        Frame, Transport,
+    };
+
+    #[tokio::test]
+    async fn it_streams_messages() {
+        let bytes = b"Hello World!";
+        let (first, used) = Frame::first(bytes);
+        let (second, _) = Frame::consecutive(1, &bytes[used..]).unwrap();
+        let mock = Mock::new(
+            Vec::new(),
+            vec![Ok(Frame::single(b"hello").unwrap()), Ok(first), Ok(second)],
+        );
+
+        let messages: Vec<Vec<u8>> = MessageStream::new(mock.reader())
+            .map(|message| message.ok().unwrap())
+            .collect()
+            .await;
+
+        assert_eq!(messages, [b"hello".to_vec(), bytes.to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn it_limits_vec_messages() {
+        // A first frame announcing a message of 4 GiB
+        let first = Frame::from_bytes(&[0x10, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0]);
+        let mock = Mock::new(Vec::new(), vec![Ok(first)]);
+        let sent = mock.sent();
+
+        let mut stream = MessageStream::<_, _, _, Vec<u8>>::new(mock.reader());
+
+        let error = stream.next().await.unwrap().err().unwrap();
+        assert_eq!(error, Error::Overflow { len: u32::MAX });
+        assert_eq!(
+            *sent.lock().unwrap(),
+            [Frame::flow(FlowKind::Overflow, 0, 0)]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_limits_heapless_messages() {
+        let (first, _) = Frame::first(b"Hello World!");
+        let mock = Mock::new(
+            Vec::new(),
+            vec![Ok(first), Ok(Frame::single(b"hello").unwrap())],
+        );
+        let sent = mock.sent();
+
+        let mut stream = MessageStream::<_, _, _, heapless::Vec<u8, 8>>::new(mock.reader());
+
+        let error = stream.next().await.unwrap().err().unwrap();
+        assert_eq!(error, Error::Overflow { len: 12 });
+        assert_eq!(
+            *sent.lock().unwrap(),
+            [Frame::flow(FlowKind::Overflow, 0, 0)]
+        );
+
+        let message = stream.next().await.unwrap().ok().unwrap();
+        assert_eq!(&message[..], b"hello");
+    }
+
+    #[tokio::test]
+    async fn it_sinks_messages() {
+        let mock = Mock::new(Vec::new(), vec![Ok(Frame::flow(FlowKind::Continue, 10, 0))]);
+        let sent = mock.sent();
+
+        let mut sink = MessageSink::new(mock.writer(MockDelay));
+        let message = b"Hello World!";
+        sink.send(b"hi".to_vec()).await.unwrap();
+        sink.send(message.to_vec()).await.unwrap();
+
+        let (first, used) = Frame::first(message);
+        let (second, _) = Frame::consecutive(1, &message[used..]).unwrap();
+        assert_eq!(
+            *sent.lock().unwrap(),
+            [Frame::single(b"hi").unwrap(), first, second]
+        );
+    }
+}
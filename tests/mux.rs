@@ -0,0 +1,167 @@
+#![cfg(all(feature = "socket", feature = "transport"))]
+
+mod can;
+mod common;
+
+use can::{id, Bus, CanFrame};
+use common::MockDelay;
+use embedded_hal::can::{Frame, Id};
+use futures::{
+    future::{self, Either},
+    StreamExt,
+};
+use iso_tp::{
+    mux::{Channel, SendError},
+    transport::{Reader, Writer},
+    Mux,
+};
+use std::pin::pin;
+
+#[tokio::test]
+async fn it_routes_frames_by_id() {
+    let bus = Bus::stalled(vec![
+        CanFrame::new(id(0x7E9), &[0x01, 0xBB]).unwrap(),
+        CanFrame::new(id(0x123), &[0x01, 0xCC]).unwrap(),
+        CanFrame::new(id(0x7E8), &[0x01, 0xAA]).unwrap(),
+    ]);
+    let mux = Mux::new([
+        Channel::new(id(0x7E0), id(0x7E8)),
+        Channel::new(id(0x7E1), id(0x7E9)),
+    ]);
+
+    let (mut first, mut second) = (mux.channel(0), mux.channel(1));
+    let read = async {
+        let a = first.next().await.unwrap().unwrap();
+        let b = second.next().await.unwrap().unwrap();
+        (a, b)
+    };
+
+    let Either::Right(((a, b), _)) = future::select(pin!(mux.run(bus)), pin!(read)).await else {
+        panic!("the mux stopped");
+    };
+    assert_eq!(a.single_data(), &[0xAA]);
+    assert_eq!(b.single_data(), &[0xBB]);
+}
+
+#[tokio::test]
+async fn it_receives_while_another_channel_is_full() {
+    let mut frames: Vec<_> = (0..10)
+        .map(|n| CanFrame::new(id(0x7E8), &[0x01, n]).unwrap())
+        .collect();
+    frames.push(CanFrame::new(id(0x7E9), &[0x01, 0xBB]).unwrap());
+    let bus = Bus::stalled(frames);
+    let mux = Mux::new([
+        Channel::new(id(0x7E0), id(0x7E8)),
+        Channel::new(id(0x7E1), id(0x7E9)),
+    ]);
+
+    // The first channel is never read while the second one is
+    let mut second = mux.channel(1);
+    let Either::Right((frame, _)) = future::select(pin!(mux.run(bus)), pin!(second.next())).await
+    else {
+        panic!("the mux stopped");
+    };
+    assert_eq!(frame.unwrap().unwrap().single_data(), &[0xBB]);
+    assert_eq!(second.dropped(), 0);
+
+    let mut first = mux.channel(0);
+    assert_eq!(first.dropped(), 2);
+    for n in 0..8 {
+        let frame = first.next().await.unwrap().unwrap();
+        assert_eq!(frame.single_data(), &[n]);
+    }
+}
+
+#[tokio::test]
+async fn it_serves_transports_on_each_channel() {
+    let bus = Bus::stalled(vec![
+        CanFrame::new(id(0x7E9), &[0x02, 0x41, 0x00]).unwrap(),
+        CanFrame::new(id(0x7E8), &[0x30, 0x00, 0x00]).unwrap(),
+    ]);
+    let sent = bus.tx.clone();
+    let mux = Mux::new([
+        Channel::new(id(0x7E0), id(0x7E8)),
+        Channel::new(id(0x7E1), id(0x7E9)),
+    ]);
+
+    let mut writer = Writer::new(mux.channel(0), MockDelay);
+    let mut reader = Reader::new(mux.channel(1));
+    let mut buf = [0; 8];
+    let io = async {
+        let (written, read) = future::join(
+            writer.write_message(b"Hello World!"),
+            reader.read_message(&mut buf),
+        )
+        .await;
+        written.unwrap();
+        read.unwrap()
+    };
+
+    let Either::Right((len, _)) = future::select(pin!(mux.run(bus)), pin!(io)).await else {
+        panic!("the mux stopped");
+    };
+    assert_eq!(&buf[..len], &[0x41, 0x00]);
+
+    let sent = sent.lock().unwrap();
+    let ids: Vec<_> = sent.iter().map(|frame| frame.id).collect();
+    assert_eq!(ids, [Id::from(id(0x7E0)); 2]);
+    assert_eq!(sent[0].data[0], 0x10);
+    assert_eq!(sent[1].data[0], 0x21);
+}
+
+#[tokio::test]
+async fn it_closes_handles_when_stopped() {
+    let mux = Mux::new([Channel::new(id(0x7E0), id(0x7E8))]);
+    mux.run(Bus::new(Vec::new())).await.unwrap();
+
+    let mut channel = mux.channel(0);
+    assert!(channel.next().await.is_none());
+    assert_eq!(
+        futures::SinkExt::send(&mut channel, iso_tp::Frame::single(&[1]).unwrap()).await,
+        Err(SendError::Closed)
+    );
+}
+
+#[tokio::test]
+async fn it_fails_to_send_frames_too_long_for_the_controller() {
+    let mux = Mux::new([Channel::new(id(0x7E0), id(0x7E8))]);
+    let mut channel = mux.channel(0);
+
+    let frame = iso_tp::frame::Format::CAN_FD.single(&[0; 12]).unwrap();
+    let send = futures::SinkExt::send(&mut channel, frame);
+    let Either::Right((result, _)) =
+        future::select(pin!(mux.run(Bus::stalled(Vec::new()))), pin!(send)).await
+    else {
+        panic!("the mux stopped");
+    };
+    assert_eq!(result, Err(SendError::FrameTooLong));
+}
+
+#[tokio::test]
+async fn it_rejects_frames_in_another_format() {
+    let format = iso_tp::frame::Format::CAN.with_addressing(iso_tp::frame::Addressing::Mixed(0xF1));
+    let mux = Mux::new([Channel::with_format(id(0x600), id(0x600), format)]);
+    let mut channel = mux.channel(0);
+
+    let frame = iso_tp::Frame::single(b"abc").unwrap();
+    assert_eq!(
+        futures::SinkExt::send(&mut channel, frame).await,
+        Err(SendError::AddressMismatch)
+    );
+}
+
+#[cfg(feature = "alloc")]
+#[tokio::test]
+async fn it_adds_channels_to_a_vec() {
+    let bus = Bus::stalled(vec![CanFrame::new(id(0x7E9), &[0x01, 0xBB]).unwrap()]);
+    let mux = Mux::new(Vec::new());
+    mux.push(Channel::new(id(0x7E0), id(0x7E8)));
+    let mut channel = mux.push(Channel::new(id(0x7E1), id(0x7E9)));
+    assert_eq!(channel.index(), 1);
+
+    let Either::Right((frame, _)) = future::select(pin!(mux.run(bus)), pin!(channel.next())).await
+    else {
+        panic!("the mux stopped");
+    };
+    assert_eq!(frame.unwrap().unwrap().single_data(), &[0xBB]);
+}
@@ -1,88 +1,606 @@
-use std::{
-    convert::Infallible,
-    pin::Pin,
-    task::{Context, Poll},
-    vec,
-};
-
-use futures::{stream, Sink, SinkExt, Stream, StreamExt};
-use iso_tp::Frame;
-
-struct Mock {
-    tx: Vec<Frame>,
-    rx: stream::Iter<vec::IntoIter<Result<Frame, ()>>>,
-}
+#![cfg(feature = "transport")]
 
-impl Mock {
-    pub fn new(tx: Vec<Frame>, rx: Vec<Result<Frame, ()>>) -> Self {
-        Self {
-            tx,
-            rx: stream::iter(rx),
-        }
+mod common;
+
+mod tests {
+    use crate::common::{Mock, MockDelay, PendingTimer};
+    use async_hal::io::AsyncRead;
+    use core::time::Duration;
+    use iso_tp::{
+        frame::{Addressing, FlowKind, Format, Padding, ParseError},
+        transport::timer::{NoTimer, TimeoutKind},
+        transport::{
+            reader::{Config, Error, MessageKind},
+            Reader,
+        },
+        Frame, Transport,
+    };
+
+    #[tokio::test]
+    async fn it_reads_single_frames() {
+        let bytes = b"example";
+        let frame = Frame::single(bytes).unwrap();
+
+        let mock = Mock::new(Vec::new(), vec![Ok(frame)]);
+        let mut reader = mock.reader();
+
+        let mut buf = [0; 7];
+        let used = reader.read(&mut buf).await.ok().unwrap();
+        reader.read(&mut buf[used..]).await.ok().unwrap();
+
+        assert_eq!(&buf, bytes);
     }
-}
 
-impl Stream for Mock {
-    type Item = Result<Frame, ()>;
+    #[tokio::test]
+    async fn it_reads_consecutive_frames() {
+        let bytes = b"Hello World!";
+        let (first, used) = Frame::first(bytes);
+        let (second, _) = Frame::consecutive(1, &bytes[used..]).unwrap();
+        let mock = Mock::new(Vec::new(), vec![Ok(first), Ok(second)]);
 
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        self.rx.poll_next_unpin(cx)
+        let mut reader = mock.reader();
+        let mut buf = [0; 12];
+        let used = reader.read(&mut buf).await.ok().unwrap();
+        reader.read(&mut buf[used..]).await.ok().unwrap();
+
+        assert_eq!(&buf, bytes);
     }
-}
 
-impl Sink<Frame> for Mock {
-    type Error = Infallible;
+    #[tokio::test]
+    async fn it_reads_escaped_first_frames() {
+        let bytes: Vec<u8> = (0..5000).map(|n| n as u8).collect();
+        let (first, mut pos) = Frame::first(&bytes);
+
+        let mut frames = vec![Ok(first)];
+        let mut index = 1usize;
+        while pos < bytes.len() {
+            let (frame, used) = Frame::consecutive((index % 16) as u8, &bytes[pos..]).unwrap();
+            frames.push(Ok(frame));
+            pos += used;
+            index += 1;
+        }
+
+        let mock = Mock::new(Vec::new(), frames);
+        let mut reader = mock.reader();
+
+        let mut buf = vec![0; bytes.len()];
+        let mut used = 0;
+        while used < buf.len() {
+            used += reader.read(&mut buf[used..]).await.ok().unwrap();
+        }
 
-    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.tx.poll_ready_unpin(cx)
+        assert_eq!(buf, bytes);
     }
 
-    fn start_send(mut self: Pin<&mut Self>, item: Frame) -> Result<(), Self::Error> {
-        self.tx.start_send_unpin(item)
+    #[tokio::test]
+    async fn it_rejects_malformed_frames() {
+        let frame = Frame::from_bytes(&[0x0F, 1, 2, 3, 4, 5, 6, 7]);
+        let mock = Mock::new(Vec::new(), vec![Ok(frame)]);
+        let mut reader = mock.reader();
+
+        let mut buf = [0; 8];
+        let error = reader.read(&mut buf).await.err().unwrap();
+        assert_eq!(error, Error::Parse(ParseError::InvalidSingleLength(15)));
     }
 
-    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.tx.poll_flush_unpin(cx)
+    #[tokio::test]
+    async fn it_reads_with_mixed_addressing() {
+        let format = Format::CAN.with_addressing(Addressing::Mixed(0x42));
+        let bytes = b"Hello World!";
+        let (first, used) = format.first(bytes);
+        let (second, used2) = format.consecutive(1, &bytes[used..]).unwrap();
+        let (third, _) = format.consecutive(2, &bytes[used + used2..]).unwrap();
+        let mock = Mock::new(Vec::new(), vec![Ok(first), Ok(second), Ok(third)]);
+
+        let sent = mock.sent();
+
+        let mut reader = Reader::with_format(mock, format);
+        let mut buf = [0; 12];
+        let mut used = 0;
+        while used < buf.len() {
+            used += reader.read(&mut buf[used..]).await.ok().unwrap();
+        }
+
+        assert_eq!(&buf, bytes);
+        assert_eq!(
+            sent.lock().unwrap()[0],
+            format.flow(FlowKind::Continue, 10, 0)
+        );
+        assert_eq!(sent.lock().unwrap()[0].address(), Some(0x42));
     }
 
-    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.tx.poll_close_unpin(cx)
+    #[tokio::test]
+    async fn it_reads_short_segmented_messages_with_extended_addressing() {
+        use async_hal::io::AsyncWrite;
+        use iso_tp::transport::Writer;
+
+        let format = Format::CAN.with_addressing(Addressing::Extended {
+            target: 0xF1,
+            source: 0x10,
+        });
+        let bytes = b"example";
+        let writer_mock = Mock::new(Vec::new(), vec![Ok(format.flow(FlowKind::Continue, 0, 0))]);
+        let sent = writer_mock.sent();
+        let mut writer = Writer::with_format(writer_mock, MockDelay, format);
+        writer.write_all(bytes).await.ok().unwrap();
+
+        // Seven bytes don't fit in a single frame after the address byte
+        let frames: Vec<_> = sent.lock().unwrap().drain(..).map(Ok).collect();
+        assert_eq!(frames.len(), 2);
+
+        let mut reader = Reader::with_format(Mock::new(Vec::new(), frames), format);
+        let mut buf = [0; 7];
+        assert_eq!(reader.read_message(&mut buf).await.ok(), Some(7));
+        assert_eq!(&buf, bytes);
     }
-}
 
-#[cfg(feature = "socket")]
-mod tests {
-    use crate::Mock;
-    use async_hal::io::AsyncRead;
-    use iso_tp::{Frame, Transport};
+    #[tokio::test]
+    async fn it_sends_configured_flow_control() {
+        let bytes: Vec<u8> = (0..100).collect();
+        let (first, mut pos) = Frame::first(&bytes);
+
+        let mut frames = vec![Ok(first)];
+        let mut index = 1;
+        while pos < bytes.len() {
+            let (frame, used) = Frame::consecutive(index % 16, &bytes[pos..]).unwrap();
+            frames.push(Ok(frame));
+            pos += used;
+            index += 1;
+        }
+
+        let mock = Mock::new(Vec::new(), frames);
+        let sent = mock.sent();
+
+        let config = Config::builder()
+            .block_len(0)
+            .st(Duration::from_micros(250))
+            .build();
+        let mut reader = Reader::with_config(mock, config);
+
+        let mut buf = vec![0; bytes.len()];
+        let mut used = 0;
+        while used < buf.len() {
+            used += reader.read(&mut buf[used..]).await.ok().unwrap();
+        }
+
+        assert_eq!(buf, bytes);
+        assert_eq!(
+            *sent.lock().unwrap(),
+            [Frame::flow(FlowKind::Continue, 0, 0xF3)]
+        );
+    }
 
     #[tokio::test]
-    async fn it_reads_single_frames() {
-        let bytes = b"example";
-        let frame = Frame::single(bytes).unwrap();
+    async fn it_rejects_wrong_sequence_numbers() {
+        let bytes = b"Hello World! Hello World!";
+        let (first, used) = Frame::first(bytes);
+        let (second, _) = Frame::consecutive(2, &bytes[used..]).unwrap();
+        let mock = Mock::new(Vec::new(), vec![Ok(first), Ok(second)]);
 
-        let mock = Mock::new(Vec::new(), vec![Ok(frame)]);
         let mut reader = mock.reader();
+        let mut buf = [0; 25];
+        reader.read(&mut buf).await.ok().unwrap();
+
+        let error = reader.read(&mut buf).await.err().unwrap();
+        assert_eq!(
+            error,
+            Error::WrongSequenceNumber {
+                expected: 1,
+                received: 2
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn it_times_out_waiting_for_consecutive_frames() {
+        let (first, _) = Frame::first(b"Hello World!");
+        let mock = Mock::stalled(vec![Ok(first)]);
+
+        let mut reader = Reader::with_timer(mock, MockDelay, Config::default());
+        let mut buf = [0; 12];
+        reader.read(&mut buf).await.ok().unwrap();
+
+        let error = reader.read(&mut buf).await.err().unwrap();
+        assert_eq!(error, Error::Timeout(TimeoutKind::Cr));
+    }
+
+    #[tokio::test]
+    async fn it_rejects_messages_over_max_message_len() {
+        let (first, _) = Frame::first(&[0; 100]);
+        let mock = Mock::new(
+            Vec::new(),
+            vec![Ok(first), Ok(Frame::single(b"hello").unwrap())],
+        );
+        let sent = mock.sent();
+
+        let config = Config::builder().max_message_len(64).build();
+        let mut reader = Reader::with_config(mock, config);
+        let mut buf = [0; 64];
+
+        let error = reader.read(&mut buf).await.err().unwrap();
+        assert_eq!(error, Error::Overflow { len: 100 });
+        assert_eq!(
+            *sent.lock().unwrap(),
+            [Frame::flow(FlowKind::Overflow, 0, 0)]
+        );
 
-        let mut buf = [0; 7];
         let used = reader.read(&mut buf).await.ok().unwrap();
+        assert_eq!(&buf[..used], b"hello");
+    }
+
+    #[tokio::test]
+    async fn it_holds_off_the_sender() {
+        let bytes = b"Hello World!";
+        let (first, used) = Frame::first(bytes);
+        let (second, _) = Frame::consecutive(1, &bytes[used..]).unwrap();
+        let mock = Mock::new(Vec::new(), vec![Ok(first), Ok(second)]);
+        let sent = mock.sent();
+
+        let mut reader = Reader::with_timer(mock, PendingTimer, Config::default());
+        let mut buf = [0; 12];
+        let used = reader.read(&mut buf).await.ok().unwrap();
+
+        reader.hold(tokio::task::yield_now()).await.ok().unwrap();
         reader.read(&mut buf[used..]).await.ok().unwrap();
 
         assert_eq!(&buf, bytes);
+        assert_eq!(
+            *sent.lock().unwrap(),
+            [
+                Frame::flow(FlowKind::Wait, 0, 0),
+                Frame::flow(FlowKind::Continue, 10, 0)
+            ]
+        );
     }
 
     #[tokio::test]
-    async fn it_reads_consecutive_frames() {
+    async fn it_holds_off_the_sender_after_the_header() {
         let bytes = b"Hello World!";
         let (first, used) = Frame::first(bytes);
-        let (second, _) = Frame::consecutive(0, &bytes[used..]);
+        let (second, _) = Frame::consecutive(1, &bytes[used..]).unwrap();
         let mock = Mock::new(Vec::new(), vec![Ok(first), Ok(second)]);
+        let sent = mock.sent();
+
+        let mut reader = Reader::with_timer(mock, PendingTimer, Config::default());
+        let header = reader.next_message_header().await.ok().unwrap();
+
+        let mut buf = reader
+            .hold(async {
+                tokio::task::yield_now().await;
+                vec![0; header.len() as usize]
+            })
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(reader.read_message(&mut buf).await.ok(), Some(12));
+
+        assert_eq!(buf, bytes);
+        assert_eq!(
+            *sent.lock().unwrap(),
+            [
+                Frame::flow(FlowKind::Wait, 0, 0),
+                Frame::flow(FlowKind::Continue, 10, 0)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_needs_a_timer_to_hold_off_the_sender() {
+        let (first, _) = Frame::first(b"Hello World!");
+        let mock = Mock::new(Vec::new(), vec![Ok(first)]);
+        let sent = mock.sent();
 
         let mut reader = mock.reader();
         let mut buf = [0; 12];
+        reader.read(&mut buf).await.ok().unwrap();
+
+        let error = reader
+            .hold(core::future::pending::<()>())
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(error, Error::MissingTimer);
+        assert!(sent.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_limits_wait_frames() {
+        let (first, _) = Frame::first(b"Hello World!");
+        let mock = Mock::new(Vec::new(), vec![Ok(first)]);
+        let sent = mock.sent();
+
+        let config = Config::builder().n_wft_max(2).build();
+        let mut reader = Reader::with_timer(mock, MockDelay, config);
+        let mut buf = [0; 12];
+        reader.read(&mut buf).await.ok().unwrap();
+
+        let error = reader
+            .hold(core::future::pending::<()>())
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(error, Error::WaitLimitExceeded);
+        assert_eq!(
+            *sent.lock().unwrap(),
+            [
+                Frame::flow(FlowKind::Wait, 0, 0),
+                Frame::flow(FlowKind::Wait, 0, 0)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_receives_messages_back_to_back() {
+        let bytes = b"Hello World!";
+        let (first, used) = Frame::first(bytes);
+        let (second, _) = Frame::consecutive(1, &bytes[used..]).unwrap();
+        let mut mock = Mock::new(
+            Vec::new(),
+            vec![Ok(Frame::single(b"hello").unwrap()), Ok(first), Ok(second)],
+        );
+
+        let mut buf = [0; 64];
+        let len = mock
+            .recv_message(NoTimer, Config::default(), &mut buf)
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(&buf[..len], b"hello");
+
+        let len = mock
+            .recv_message(NoTimer, Config::default(), &mut buf)
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(&buf[..len], bytes);
+    }
+
+    #[tokio::test]
+    async fn it_receives_messages_that_interrupt_the_current_one() {
+        let (first, _) = Frame::first(b"Hello World!");
+        let mut mock = Mock::new(
+            Vec::new(),
+            vec![
+                Ok(first),
+                Ok(Frame::single(b"new").unwrap()),
+                Ok(Frame::single(b"later").unwrap()),
+            ],
+        );
+
+        let mut buf = [0; 64];
+        let len = mock
+            .recv_message(NoTimer, Config::default(), &mut buf)
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(&buf[..len], b"new");
+    }
+
+    #[tokio::test]
+    async fn it_rejects_messages_longer_than_the_buffer() {
+        let (first, _) = Frame::first(b"Hello World!");
+        let mut mock = Mock::new(Vec::new(), vec![Ok(first)]);
+        let sent = mock.sent();
+
+        let mut buf = [0; 8];
+        let error = mock
+            .recv_message(NoTimer, Config::default(), &mut buf)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(error, Error::Overflow { len: 12 });
+        assert_eq!(
+            *sent.lock().unwrap(),
+            [Frame::flow(FlowKind::Overflow, 0, 0)]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_reads_multiple_messages() {
+        let bytes = b"Hello World!";
+        let (first, used) = Frame::first(bytes);
+        let (second, _) = Frame::consecutive(1, &bytes[used..]).unwrap();
+        let mock = Mock::new(
+            Vec::new(),
+            vec![Ok(Frame::single(b"hello").unwrap()), Ok(first), Ok(second)],
+        );
+        let mut reader = mock.reader();
+
+        let mut buf = [0; 12];
+        assert_eq!(reader.read(&mut buf).await.ok(), Some(5));
+        assert_eq!(reader.read(&mut buf).await.ok(), Some(0));
+        assert_eq!(&buf[..5], b"hello");
+
+        let mut used = 0;
+        loop {
+            match reader.read(&mut buf[used..]).await.ok().unwrap() {
+                0 => break,
+                n => used += n,
+            }
+        }
+        assert_eq!(&buf[..used], bytes);
+    }
+
+    #[tokio::test]
+    async fn it_resets_the_current_message() {
+        let (first, _) = Frame::first(b"Hello World!");
+        let mock = Mock::new(
+            Vec::new(),
+            vec![Ok(first), Ok(Frame::single(b"hello").unwrap())],
+        );
+        let mut reader = mock.reader();
+
+        let mut buf = [0; 12];
+        reader.read(&mut buf).await.ok().unwrap();
+        reader.reset();
+
         let used = reader.read(&mut buf).await.ok().unwrap();
-        reader.read(&mut buf[used..]).await.ok().unwrap();
+        assert_eq!(&buf[..used], b"hello");
+    }
+
+    #[tokio::test]
+    async fn it_reads_messages_that_interrupt_the_current_one() {
+        let bytes = b"Hello World!";
+        let (interrupted, _) = Frame::first(b"Interrupted!");
+        let (first, used) = Frame::first(bytes);
+        let (second, _) = Frame::consecutive(1, &bytes[used..]).unwrap();
+        let mock = Mock::new(Vec::new(), vec![Ok(interrupted), Ok(first), Ok(second)]);
+        let mut reader = mock.reader();
 
+        let mut buf = [0; 12];
+        reader.read(&mut buf).await.ok().unwrap();
+
+        let error = reader.read(&mut buf).await.err().unwrap();
+        assert_eq!(error, Error::Interrupted);
+
+        let mut used = 0;
+        while used < buf.len() {
+            used += reader.read(&mut buf[used..]).await.ok().unwrap();
+        }
+        assert_eq!(&buf, bytes);
+    }
+
+    #[tokio::test]
+    async fn it_reads_into_small_buffers() {
+        let bytes = b"Hello World!";
+        let (first, used) = Frame::first(bytes);
+        let (second, _) = Frame::consecutive(1, &bytes[used..]).unwrap();
+        let mock = Mock::new(
+            Vec::new(),
+            vec![Ok(Frame::single(b"hello").unwrap()), Ok(first), Ok(second)],
+        );
+        let mut reader = mock.reader();
+
+        for expected in [&b"hello"[..], bytes] {
+            let mut message = Vec::new();
+            let mut buf = [0; 4];
+            loop {
+                match reader.read(&mut buf).await.ok().unwrap() {
+                    0 => break,
+                    n => message.extend_from_slice(&buf[..n]),
+                }
+            }
+            assert_eq!(message, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_returns_message_headers() {
+        let bytes = b"Hello World!";
+        let (first, used) = Frame::first(bytes);
+        let (second, _) = Frame::consecutive(1, &bytes[used..]).unwrap();
+        let mock = Mock::new(
+            Vec::new(),
+            vec![Ok(Frame::single(b"hello").unwrap()), Ok(first), Ok(second)],
+        );
+        let sent = mock.sent();
+        let mut reader = mock.reader();
+
+        let header = reader.next_message_header().await.ok().unwrap();
+        assert_eq!((header.len(), header.kind()), (5, MessageKind::Single));
+        assert_eq!(header.address(), None);
+
+        let mut buf = [0; 12];
+        reader.read(&mut buf).await.ok().unwrap();
+        assert_eq!(&buf[..5], b"hello");
+
+        let header = reader.next_message_header().await.ok().unwrap();
+        assert_eq!((header.len(), header.kind()), (12, MessageKind::Segmented));
+        assert!(sent.lock().unwrap().is_empty());
+
+        let mut used = 0;
+        while used < buf.len() {
+            used += reader.read(&mut buf[used..]).await.ok().unwrap();
+        }
         assert_eq!(&buf, bytes);
     }
+
+    #[tokio::test]
+    async fn it_reads_messages_after_their_header() {
+        let bytes = b"Hello World!";
+        let (first, used) = Frame::first(bytes);
+        let (second, _) = Frame::consecutive(1, &bytes[used..]).unwrap();
+        let mock = Mock::new(
+            Vec::new(),
+            vec![
+                Ok(Frame::single(b"hello").unwrap()),
+                Ok(first),
+                Ok(second),
+                Ok(Frame::single(b"later").unwrap()),
+            ],
+        );
+        let mut reader = mock.reader();
+
+        let header = reader.next_message_header().await.ok().unwrap();
+        let mut buf = vec![0; header.len() as usize];
+        assert_eq!(reader.read_message(&mut buf).await.ok(), Some(5));
+        assert_eq!(buf, b"hello");
+
+        let header = reader.next_message_header().await.ok().unwrap();
+        let mut buf = vec![0; header.len() as usize];
+        assert_eq!(reader.read_message(&mut buf).await.ok(), Some(12));
+        assert_eq!(buf, bytes);
+    }
+
+    #[tokio::test]
+    async fn it_rejects_messages_after_their_header_that_dont_fit() {
+        let (first, _) = Frame::first(b"Hello World!");
+        let mock = Mock::new(
+            Vec::new(),
+            vec![Ok(first), Ok(Frame::single(b"hello").unwrap())],
+        );
+        let sent = mock.sent();
+        let mut reader = mock.reader();
+
+        reader.next_message_header().await.ok().unwrap();
+        let mut buf = [0; 8];
+        let error = reader.read_message(&mut buf).await.err().unwrap();
+        assert_eq!(error, Error::Overflow { len: 12 });
+        assert_eq!(
+            *sent.lock().unwrap(),
+            [Frame::flow(FlowKind::Overflow, 0, 0)]
+        );
+
+        assert_eq!(reader.read_message(&mut buf).await.ok(), Some(5));
+        assert_eq!(&buf[..5], b"hello");
+    }
+
+    #[tokio::test]
+    async fn it_rejects_messages_after_their_header() {
+        let (first, _) = Frame::first(b"Hello World!");
+        let mock = Mock::new(
+            Vec::new(),
+            vec![Ok(first), Ok(Frame::single(b"hello").unwrap())],
+        );
+        let sent = mock.sent();
+        let mut reader = mock.reader();
+
+        let header = reader.next_message_header().await.ok().unwrap();
+        assert_eq!(header.len(), 12);
+        reader.reject_message().await.ok().unwrap();
+        assert_eq!(
+            *sent.lock().unwrap(),
+            [Frame::flow(FlowKind::Overflow, 0, 0)]
+        );
+
+        let mut buf = [0; 5];
+        reader.read(&mut buf).await.ok().unwrap();
+        assert_eq!(&buf, b"hello");
+    }
+
+    #[tokio::test]
+    async fn it_rejects_frames_with_wrong_padding() {
+        let mock = Mock::new(Vec::new(), vec![Ok(Frame::single(b"hello").unwrap())]);
+
+        let format = Format::CAN.with_padding(Padding::Byte(0xCC));
+        let config = Config::builder()
+            .format(format)
+            .strict_padding(true)
+            .build();
+        let mut reader = Reader::with_config(mock, config);
+
+        let mut buf = [0; 5];
+        let error = reader.read(&mut buf).await.err().unwrap();
+        assert_eq!(error, Error::Parse(ParseError::InvalidPadding));
+    }
 }
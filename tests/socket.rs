@@ -0,0 +1,334 @@
+#![cfg(feature = "socket")]
+
+mod can;
+
+use can::{extended_id, id, Bus, CanFrame};
+use embedded_hal::can::{Frame, Id};
+use futures::{stream, SinkExt, StreamExt};
+use iso_tp::{
+    frame::{Addressing, Format},
+    FixedAddress, FixedError, FixedSocket, FunctionalError, FunctionalSocket, Responder, Socket,
+    SocketError,
+};
+
+#[tokio::test]
+async fn it_drops_frames_from_other_ids() {
+    let frames = vec![
+        Ok::<_, ()>(CanFrame::new(id(0x7E9), &[0x01, 0xAA]).unwrap()),
+        Ok(CanFrame::new(id(0x7E8), &[0x01, 0xBB]).unwrap()),
+    ];
+    let mut socket: Socket<_, _, CanFrame> =
+        Socket::new(id(0x7E0), id(0x7E8), stream::iter(frames));
+
+    let frame = socket.next().await.unwrap().unwrap();
+    assert_eq!(frame.single_data(), &[0xBB]);
+    assert!(socket.next().await.is_none());
+}
+
+#[tokio::test]
+async fn it_drops_frames_for_other_addresses() {
+    let frames = vec![
+        Ok::<_, ()>(CanFrame::new(id(0x600), &[0x20, 0x01, 0xAA]).unwrap()),
+        Ok(CanFrame::new(id(0x600), &[0x10, 0x01, 0xBB]).unwrap()),
+    ];
+    let format = Format::CAN.with_addressing(Addressing::Extended {
+        target: 0x20,
+        source: 0x10,
+    });
+    let mut socket: Socket<_, _, CanFrame> =
+        Socket::with_format(id(0x600), id(0x600), stream::iter(frames), format);
+
+    let frame = socket.next().await.unwrap().unwrap();
+    assert_eq!(frame.address(), Some(0x10));
+    assert_eq!(frame.single_data(), &[0xBB]);
+    assert!(socket.next().await.is_none());
+}
+
+fn responders() -> [Responder; 8] {
+    core::array::from_fn(|i| Responder::new(id(0x7E0 + i as u16), id(0x7E8 + i as u16)))
+}
+
+#[tokio::test]
+async fn it_refuses_segmented_functional_requests() {
+    let bus = Bus::new(Vec::new());
+    let sent = bus.tx.clone();
+    let mut socket: FunctionalSocket<_, (), CanFrame, _> =
+        FunctionalSocket::new(id(0x7DF), responders(), bus);
+
+    let (frame, _) = iso_tp::Frame::first(&[0x22; 20]);
+    assert_eq!(socket.send(frame).await, Err(FunctionalError::Segmented));
+
+    let frame = iso_tp::Frame::single(&[0x01, 0x00]).unwrap();
+    socket.send(frame).await.unwrap();
+
+    let sent = sent.lock().unwrap();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].id, Id::from(id(0x7DF)));
+    assert_eq!(&sent[0].data[..3], &[0x02, 0x01, 0x00]);
+}
+
+#[tokio::test]
+async fn it_collects_responses_from_several_ids() {
+    let bus = Bus::new(vec![
+        CanFrame::new(id(0x7E8), &[0x02, 0x41, 0x00]).unwrap(),
+        CanFrame::new(id(0x123), &[0x01, 0xAA]).unwrap(),
+        CanFrame::new(id(0x7EA), &[0x02, 0x41, 0x01]).unwrap(),
+    ]);
+    let mut socket: FunctionalSocket<_, _, CanFrame, _> =
+        FunctionalSocket::new(id(0x7DF), responders(), bus);
+
+    let (index, frame) = socket.next().await.unwrap().unwrap();
+    assert_eq!(index, 0);
+    assert_eq!(frame.id(), Some(Id::from(id(0x7E8))));
+    assert_eq!(frame.single_data(), &[0x41, 0x00]);
+
+    let (index, frame) = socket.next().await.unwrap().unwrap();
+    assert_eq!(index, 2);
+    assert_eq!(frame.single_data(), &[0x41, 0x01]);
+
+    assert!(socket.next().await.is_none());
+}
+
+#[cfg(feature = "transport")]
+#[tokio::test]
+async fn it_reads_every_response_while_others_are_in_flight() {
+    let message = b"Hello World!";
+    let (first, used) = iso_tp::Frame::first(message);
+    let (consecutive, _) = iso_tp::Frame::consecutive(1, &message[used..]).unwrap();
+    let bus = Bus::new(vec![
+        CanFrame::new(id(0x7E9), first.as_ref()).unwrap(),
+        CanFrame::new(id(0x7EA), first.as_ref()).unwrap(),
+        CanFrame::new(id(0x7E8), &[0x02, 0x41, 0x00]).unwrap(),
+        CanFrame::new(id(0x7EA), consecutive.as_ref()).unwrap(),
+        CanFrame::new(id(0x7E9), consecutive.as_ref()).unwrap(),
+    ]);
+    let sent = bus.tx.clone();
+    let mut socket: FunctionalSocket<_, _, CanFrame, _> =
+        FunctionalSocket::new(id(0x7DF), responders(), bus);
+
+    let mut responses = Vec::new();
+    while let Some(response) = socket.next().await {
+        let (index, frame) = response.unwrap();
+        let mut reader = socket.responder(index).reader();
+        reader.push_frame(frame);
+
+        let mut buf = [0; 12];
+        let len = reader.read_message(&mut buf).await.unwrap();
+        responses.push((index, buf[..len].to_vec()));
+    }
+
+    assert_eq!(
+        responses,
+        [
+            (1, message.to_vec()),
+            (0, vec![0x41, 0x00]),
+            (2, message.to_vec())
+        ]
+    );
+
+    // Flow control is sent to the physical identifier of each responder
+    let sent = sent.lock().unwrap();
+    let ids: Vec<_> = sent.iter().map(|frame| frame.id).collect();
+    assert_eq!(ids, [Id::from(id(0x7E1)), Id::from(id(0x7E2))]);
+    assert!(socket
+        .responders()
+        .iter()
+        .all(|responder| responder.dropped() == 0));
+}
+
+#[test]
+fn it_encodes_fixed_addresses() {
+    let address = FixedAddress::physical(0x10, 0xF1);
+    assert_eq!(address.to_id(), extended_id(0x18DA10F1));
+    assert_eq!(
+        FixedAddress::from_id(extended_id(0x18DA10F1)),
+        Some(address)
+    );
+    assert_eq!(address.reply(0x10).to_id(), extended_id(0x18DAF110));
+
+    let address = FixedAddress::functional(0x33, 0xF1);
+    assert_eq!(address.to_id(), extended_id(0x18DB33F1));
+    assert_eq!(
+        FixedAddress::from_id(extended_id(0x18DB33F1)),
+        Some(address)
+    );
+
+    // Other priorities are accepted
+    assert_eq!(
+        FixedAddress::from_id(extended_id(0x1CDA10F1)),
+        Some(FixedAddress::physical(0x10, 0xF1))
+    );
+
+    assert_eq!(FixedAddress::from_id(extended_id(0x18EA10F1)), None);
+    assert_eq!(FixedAddress::from_id(id(0x7DF)), None);
+}
+
+#[tokio::test]
+async fn it_replies_to_any_fixed_address_peer() {
+    let bus = Bus::new(vec![
+        CanFrame::new(extended_id(0x18DA20F1), &[0x02, 0x10, 0x01]).unwrap(),
+        CanFrame::new(extended_id(0x18DA10F1), &[0x02, 0x10, 0x03]).unwrap(),
+        CanFrame::new(extended_id(0x18DB33F2), &[0x02, 0x01, 0x00]).unwrap(),
+    ]);
+    let sent = bus.tx.clone();
+    let mut socket: FixedSocket<_, _, CanFrame> =
+        FixedSocket::new(0x10, bus).with_functional_address(0x33);
+
+    let frame = socket.next().await.unwrap().unwrap();
+    assert_eq!(frame.single_data(), &[0x10, 0x03]);
+    assert_eq!(socket.peer(), Some(FixedAddress::physical(0x10, 0xF1)));
+    socket
+        .send(iso_tp::Frame::single(&[0x50, 0x03]).unwrap())
+        .await
+        .unwrap();
+
+    let frame = socket.next().await.unwrap().unwrap();
+    assert_eq!(frame.single_data(), &[0x01, 0x00]);
+    assert_eq!(socket.peer(), Some(FixedAddress::functional(0x33, 0xF2)));
+    socket
+        .send(iso_tp::Frame::single(&[0x41, 0x00]).unwrap())
+        .await
+        .unwrap();
+
+    assert!(socket.next().await.is_none());
+
+    let sent = sent.lock().unwrap();
+    assert_eq!(sent[0].id, Id::Extended(extended_id(0x18DAF110)));
+    assert_eq!(sent[1].id, Id::Extended(extended_id(0x18DAF210)));
+}
+
+#[cfg(feature = "transport")]
+#[tokio::test]
+async fn it_returns_the_peer_of_each_message() {
+    use async_hal::io::AsyncRead;
+
+    let (first, used) = iso_tp::Frame::first(b"Hello World!");
+    let (consecutive, _) = iso_tp::Frame::consecutive(1, &b"Hello World!"[used..]).unwrap();
+    let bus = Bus::new(vec![
+        CanFrame::new(extended_id(0x18DA10F1), &[0x02, 0x10, 0x03]).unwrap(),
+        CanFrame::new(extended_id(0x18DA10F2), first.as_ref()).unwrap(),
+        CanFrame::new(extended_id(0x18DA10F2), consecutive.as_ref()).unwrap(),
+    ]);
+    let sent = bus.tx.clone();
+    let mut reader = FixedSocket::<_, _, CanFrame>::new(0x10, bus).reader();
+
+    let peer = |id| match id {
+        Some(Id::Extended(id)) => FixedAddress::from_id(id).map(|peer| peer.source()),
+        _ => None,
+    };
+
+    let mut buf = [0; 12];
+    let header = reader.next_message_header().await.unwrap();
+    assert_eq!(peer(header.id()), Some(0xF1));
+    assert_eq!(reader.read(&mut buf).await, Ok(2));
+
+    let header = reader.next_message_header().await.unwrap();
+    assert_eq!(peer(header.id()), Some(0xF2));
+    let mut used = 0;
+    while used < header.len() as usize {
+        used += reader.read(&mut buf[used..]).await.unwrap();
+    }
+    assert_eq!(
+        reader.get_ref().peer().map(|peer| peer.source()),
+        Some(0xF2)
+    );
+
+    // Flow control is sent to the peer of the segmented message
+    let sent = sent.lock().unwrap();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].id, Id::Extended(extended_id(0x18DAF210)));
+}
+
+#[cfg(feature = "transport")]
+#[tokio::test]
+async fn it_keeps_the_peer_until_a_segmented_message_is_done() {
+    let (first, used) = iso_tp::Frame::first(b"Hello World!");
+    let (consecutive, _) = iso_tp::Frame::consecutive(1, &b"Hello World!"[used..]).unwrap();
+    let bus = Bus::new(vec![
+        CanFrame::new(extended_id(0x18DA10F1), first.as_ref()).unwrap(),
+        CanFrame::new(extended_id(0x18DA10F2), &[0x02, 0x10, 0x03]).unwrap(),
+        CanFrame::new(extended_id(0x18DA10F1), consecutive.as_ref()).unwrap(),
+        CanFrame::new(extended_id(0x18DA10F2), &[0x02, 0x10, 0x01]).unwrap(),
+    ]);
+    let sent = bus.tx.clone();
+    let mut reader = FixedSocket::<_, _, CanFrame>::new(0x10, bus).reader();
+
+    let mut buf = [0; 12];
+    assert_eq!(reader.read_message(&mut buf).await, Ok(12));
+    assert_eq!(&buf, b"Hello World!");
+
+    // The request from another tester during the segmented message is dropped
+    assert_eq!(reader.read_message(&mut buf).await, Ok(2));
+    assert_eq!(&buf[..2], &[0x10, 0x01]);
+
+    let sent = sent.lock().unwrap();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].id, Id::Extended(extended_id(0x18DAF110)));
+}
+
+#[tokio::test]
+async fn it_fails_to_send_without_a_peer() {
+    let mut socket: FixedSocket<_, (), CanFrame> = FixedSocket::new(0x10, Bus::new(Vec::new()));
+
+    let frame = iso_tp::Frame::single(&[0x3E, 0x00]).unwrap();
+    assert_eq!(socket.send(frame.clone()).await, Err(FixedError::NoPeer));
+
+    socket.connect(0xF1);
+    socket.send(frame).await.unwrap();
+}
+
+#[cfg(feature = "transport")]
+#[tokio::test]
+async fn it_sends_flow_control_in_the_socket_format() {
+    let format = Format::CAN.with_addressing(Addressing::Extended {
+        target: 0x20,
+        source: 0x10,
+    });
+    let (first, used) = format.first(b"Hello World");
+    let (consecutive, _) = format.consecutive(1, &b"Hello World"[used..]).unwrap();
+    let bus = Bus::new(vec![
+        CanFrame::new(id(0x600), &[&[0x10], &first.as_ref()[1..]].concat()).unwrap(),
+        CanFrame::new(id(0x600), &[&[0x10], &consecutive.as_ref()[1..]].concat()).unwrap(),
+    ]);
+    let sent = bus.tx.clone();
+    let socket: Socket<_, _, CanFrame> = Socket::with_format(id(0x600), id(0x600), bus, format);
+
+    let mut reader = socket.reader();
+    let mut buf = [0; 12];
+    assert_eq!(reader.read_message(&mut buf).await, Ok(11));
+    assert_eq!(&buf[..11], b"Hello World");
+
+    let sent = sent.lock().unwrap();
+    assert_eq!(&sent[0].data[..2], &[0x20, 0x30]);
+}
+
+#[tokio::test]
+async fn it_rejects_frames_in_another_format() {
+    let format = Format::CAN.with_addressing(Addressing::Mixed(0xF1));
+    let mut socket: Socket<_, (), CanFrame> =
+        Socket::with_format(id(0x600), id(0x600), Bus::new(Vec::new()), format);
+
+    let frame = iso_tp::Frame::single(b"abc").unwrap();
+    assert_eq!(socket.send(frame).await, Err(SocketError::AddressMismatch));
+}
+
+#[cfg(feature = "transport")]
+#[tokio::test]
+async fn it_returns_the_source_id_with_each_header() {
+    let bus = Bus::new(vec![CanFrame::new(id(0x7E8), &[0x02, 0x41, 0x00]).unwrap()]);
+    let socket: Socket<_, _, CanFrame> = Socket::new(id(0x7E0), id(0x7E8), bus);
+
+    let mut reader = socket.reader();
+    let header = reader.next_message_header().await.unwrap();
+    assert_eq!(header.len(), 2);
+    assert_eq!(header.id(), Some(Id::from(id(0x7E8))));
+}
+
+#[tokio::test]
+async fn it_fails_to_send_frames_too_long_for_the_controller() {
+    let mut socket: Socket<_, (), CanFrame> =
+        Socket::new(id(0x7E0), id(0x7E8), Bus::new(Vec::new()));
+
+    let frame = Format::CAN_FD.single(&[0; 12]).unwrap();
+    assert_eq!(socket.send(frame).await, Err(SocketError::FrameTooLong));
+}
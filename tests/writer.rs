@@ -1,66 +1,234 @@
-/*
-#[cfg(feature = "socket")]
+#![cfg(feature = "transport")]
+
+mod common;
+
 mod tests {
-    use async_hal::{delay::DelayMs, io::AsyncWrite};
-    use futures::{pin_mut, stream};
-    use iso_tp::{frame::FlowKind, Frame, Socket};
-    use std::task::Poll;
+    use crate::common::{Mock, MockDelay, MockTimer};
+    use async_hal::io::AsyncWrite;
+    use core::time::Duration;
+    use iso_tp::{
+        frame::{FlowKind, Pdu},
+        transport::{
+            timer::{NoTimer, TimeoutKind},
+            writer::{Config, Error},
+            Writer,
+        },
+        Frame, Transport,
+    };
 
-    struct MockDelay;
+    #[tokio::test]
+    async fn it_writes_single_frames() {
+        let mock = Mock::new(Vec::new(), Vec::new());
+        let sent = mock.sent();
+        let mut writer = mock.writer(MockDelay);
 
-    impl DelayMs for MockDelay {
-        type Delay = u8;
+        writer.write_all(b"hello").await.unwrap();
 
-        type Error = ();
+        assert_eq!(*sent.lock().unwrap(), [Frame::single(b"hello").unwrap()]);
+    }
 
-        fn start(&mut self, _ms: Self::Delay) -> Result<(), Self::Error> {
-            Ok(())
-        }
+    #[tokio::test]
+    async fn it_writes_consecutive_frames() {
+        let mock = Mock::new(Vec::new(), vec![Ok(Frame::flow(FlowKind::Continue, 10, 0))]);
+        let sent = mock.sent();
+        let mut writer = mock.writer(MockDelay);
 
-        fn poll_delay_ms(
-            self: std::pin::Pin<&mut Self>,
-            _cx: &mut std::task::Context,
-        ) -> std::task::Poll<Result<(), Self::Error>> {
-            Poll::Ready(Ok(()))
-        }
+        let buf = b"Hello World!";
+        writer.write_all(buf).await.unwrap();
 
-        fn cancel(&mut self) -> Result<(), Self::Error> {
-            Ok(())
-        }
+        let (first, used) = Frame::first(buf);
+        let (second, _) = Frame::consecutive(1, &buf[used..]).unwrap();
+        assert_eq!(*sent.lock().unwrap(), [first, second]);
     }
 
     #[tokio::test]
-    async fn it_writes_single_frames() {
-        let tx: Vec<Frame> = vec![];
-        let rx = stream::iter(Vec::<Result<_, ()>>::new());
+    async fn it_wraps_sequence_numbers() {
+        let mock = Mock::new(
+            Vec::new(),
+            vec![Ok(Frame::flow(FlowKind::Continue, 255, 0))],
+        );
+        let sent = mock.sent();
+        let mut writer = mock.writer(MockDelay);
 
-        let mut socket = Socket::new(tx, rx);
-        let writer = socket.writer(MockDelay);
-        pin_mut!(writer);
+        let buf: Vec<u8> = (0..200).map(|n| n as u8).collect();
+        writer.write_all(&buf).await.unwrap();
 
-        let buf = b"hello";
-        writer.write_all(buf).await.unwrap();
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent.len(), 29);
+
+        let indices: Vec<u8> = sent[1..]
+            .iter()
+            .map(|frame| match frame.parse() {
+                Ok(Pdu::Consecutive { index, .. }) => index,
+                pdu => panic!("unexpected frame {pdu:?}"),
+            })
+            .collect();
+        let expected: Vec<u8> = (1..=28).map(|n| n % 16).collect();
+        assert_eq!(indices, expected);
+    }
 
-        assert_eq!(socket.tx[0], Frame::single(b"hello").unwrap());
+    #[test]
+    fn it_rejects_invalid_sequence_numbers() {
+        assert!(Frame::consecutive(16, b"data").is_none());
     }
 
     #[tokio::test]
-    async fn it_writes_consecutive_frames() {
-        let tx: Vec<Frame> = vec![];
-        let rx = stream::iter(vec![Ok::<_, ()>(Frame::flow(FlowKind::Continue, 10, 0))]);
+    async fn it_times_out_waiting_for_flow_control() {
+        let mock = Mock::stalled(Vec::new());
+        let sent = mock.sent();
+        let mut writer = mock.writer(MockDelay);
 
-        let mut socket = Socket::new(tx, rx);
-        let writer = socket.writer(MockDelay);
-        pin_mut!(writer);
+        let error = writer.write_all(b"Hello World!").await.err().unwrap();
+        assert_eq!(error, Error::Timeout(TimeoutKind::Bs));
+        assert_eq!(sent.lock().unwrap().len(), 1);
+    }
 
-        let buf = b"Hello World!";
-        writer.write_all(buf).await.unwrap();
+    #[tokio::test]
+    async fn it_reports_overflow() {
+        let mock = Mock::new(Vec::new(), vec![Ok(Frame::flow(FlowKind::Overflow, 0, 0))]);
+        let mut writer = mock.writer(MockDelay);
 
-        let (first, used) = Frame::first(buf);
-        assert_eq!(socket.tx[0], first);
+        let error = writer.write_all(b"Hello World!").await.err().unwrap();
+        assert_eq!(error, Error::Overflow);
+    }
+
+    #[tokio::test]
+    async fn it_waits_for_flow_control() {
+        let mock = Mock::new(
+            Vec::new(),
+            vec![
+                Ok(Frame::flow(FlowKind::Wait, 0, 0)),
+                Ok(Frame::flow(FlowKind::Wait, 0, 0)),
+                Ok(Frame::flow(FlowKind::Continue, 10, 0)),
+            ],
+        );
+        let sent = mock.sent();
+
+        let config = Config::builder().n_wft_max(2).build();
+        let mut writer = Writer::with_config(mock, MockDelay, config);
+        writer.write_all(b"Hello World!").await.unwrap();
+
+        assert_eq!(sent.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn it_limits_wait_frames() {
+        let mock = Mock::new(
+            Vec::new(),
+            vec![
+                Ok(Frame::flow(FlowKind::Wait, 0, 0)),
+                Ok(Frame::flow(FlowKind::Wait, 0, 0)),
+            ],
+        );
+
+        let config = Config::builder().n_wft_max(1).build();
+        let mut writer = Writer::with_config(mock, MockDelay, config);
+
+        let error = writer.write_all(b"Hello World!").await.err().unwrap();
+        assert_eq!(error, Error::WaitLimitExceeded);
+    }
+
+    #[tokio::test]
+    async fn it_sends_unlimited_blocks() {
+        let mock = Mock::new(Vec::new(), vec![Ok(Frame::flow(FlowKind::Continue, 0, 0))]);
+        let sent = mock.sent();
+        let mut writer = mock.writer(MockDelay);
+
+        let buf: Vec<u8> = (0..200).map(|n| n as u8).collect();
+        writer.write_all(&buf).await.unwrap();
+
+        assert_eq!(sent.lock().unwrap().len(), 29);
+    }
+
+    #[tokio::test]
+    async fn it_delays_for_separation_times() {
+        for (st, delay) in [
+            (0x05, Duration::from_millis(5)),
+            (0xF3, Duration::from_micros(300)),
+            (0xFA, Duration::from_millis(127)),
+        ] {
+            let mock = Mock::new(Vec::new(), vec![Ok(Frame::flow(FlowKind::Continue, 0, st))]);
+            let timer = MockTimer::default();
+            let started = timer.started();
+            let mut writer = mock.writer(timer);
+
+            writer.write_all(&[0; 27]).await.unwrap();
+
+            assert_eq!(*started.lock().unwrap(), [delay, delay]);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_skips_separation_times_without_a_timer() {
+        let mock = Mock::new(Vec::new(), vec![Ok(Frame::flow(FlowKind::Continue, 0, 5))]);
+        let sent = mock.sent();
+        let mut writer = Writer::new(mock, NoTimer);
+
+        writer.write_all(&[0; 27]).await.unwrap();
+
+        assert_eq!(sent.lock().unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn it_sends_whole_messages() {
+        let mut mock = Mock::new(Vec::new(), vec![Ok(Frame::flow(FlowKind::Continue, 10, 0))]);
+        let sent = mock.sent();
+
+        let message = b"Hello World!";
+        mock.send_message(MockDelay, Config::default(), b"")
+            .await
+            .unwrap();
+        mock.send_message(MockDelay, Config::default(), b"hi")
+            .await
+            .unwrap();
+        mock.send_message(MockDelay, Config::default(), message)
+            .await
+            .unwrap();
+
+        let (first, used) = Frame::first(message);
+        let (second, _) = Frame::consecutive(1, &message[used..]).unwrap();
+        assert_eq!(
+            *sent.lock().unwrap(),
+            [Frame::single(b"hi").unwrap(), first, second]
+        );
+    }
+
+    #[test]
+    fn it_saturates_long_delays() {
+        use async_hal::delay::DelayMs;
+        use core::{
+            pin::Pin,
+            task::{Context, Poll},
+        };
+        use iso_tp::transport::Timer;
+
+        /// Delay that records the last started delay.
+        struct Delay(Option<u8>);
+
+        impl DelayMs for Delay {
+            type Delay = u8;
+
+            type Error = ();
+
+            fn start(&mut self, ms: Self::Delay) -> Result<(), Self::Error> {
+                self.0 = Some(ms);
+                Ok(())
+            }
+
+            fn poll_delay_ms(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), ()>> {
+                Poll::Ready(Ok(()))
+            }
+
+            fn cancel(&mut self) -> Result<(), Self::Error> {
+                Ok(())
+            }
+        }
+
+        let mut delay = Delay(None);
+        Timer::start(&mut delay, Duration::from_micros(1500)).unwrap();
+        assert_eq!(delay.0, Some(2));
 
-        let (second, _) = Frame::consecutive(0, &buf[used..]);
-        assert_eq!(socket.tx[1], second);
+        Timer::start(&mut delay, Duration::from_secs(1)).unwrap();
+        assert_eq!(delay.0, Some(u8::MAX));
     }
 }
-*/